@@ -0,0 +1,298 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{Parser, ValueEnum};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use crate::implementations::controller::StalePolicy;
+use crate::implementations::in_memory_cache::{CacheLimits, CacheTtls};
+use crate::implementations::origin_api::{CircuitBreakerPolicy, RateLimitPolicy, RetryPolicy};
+
+// Durations are either a number of seconds or a human readable string such as "5m" or "100ms"
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    match value.parse() {
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(_) => humantime::parse_duration(value).map_err(|error| format!("invalid duration `{}`: {}", value, error)),
+    }
+}
+
+struct DurationVisitor;
+
+impl Visitor<'_> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number of seconds or a duration such as \"5m\"")
+    }
+
+    fn visit_i64<E: de::Error>(self, seconds: i64) -> Result<Duration, E> {
+        u64::try_from(seconds).map(Duration::from_secs).map_err(|_| E::custom("durations can't be negative"))
+    }
+
+    fn visit_u64<E: de::Error>(self, seconds: u64) -> Result<Duration, E> {
+        Ok(Duration::from_secs(seconds))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Duration, E> {
+        parse_duration(value).map_err(E::custom)
+    }
+}
+
+pub(crate) fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+pub(crate) fn optional_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    duration(deserializer).map(Some)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Layer {
+    Memory,
+    Sqlite,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct OriginConfig {
+    pub url: String,
+    #[serde(deserialize_with = "duration")]
+    pub timeout: Duration,
+    #[serde(deserialize_with = "duration")]
+    pub connect_timeout: Duration,
+    pub retry: RetryPolicy,
+    pub rate_limit: RateLimitPolicy,
+    pub circuit_breaker: CircuitBreakerPolicy,
+}
+
+impl Default for OriginConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://api.pluralkit.me/v2".to_string(),
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            retry: RetryPolicy::default(),
+            rate_limit: RateLimitPolicy::default(),
+            circuit_breaker: CircuitBreakerPolicy::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SnapshotConfig {
+    pub path: Option<PathBuf>,
+    // Snapshots are only saved on shutdown when unset
+    #[serde(deserialize_with = "optional_duration")]
+    pub interval: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SqliteConfig {
+    pub path: Option<PathBuf>,
+    // Entries older than this are pruned at startup and every `prune_interval`
+    #[serde(deserialize_with = "duration")]
+    pub retention: Duration,
+    #[serde(deserialize_with = "duration")]
+    pub prune_interval: Duration,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            retention: Duration::from_secs(7 * 24 * 60 * 60),
+            prune_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub listen: SocketAddr,
+    // Cache layers asked before the origin, in order. Defaults to memory, then SQLite when it has a path
+    layers: Option<Vec<Layer>>,
+    // Dispatch events are only accepted once set
+    pub signing_token: Option<String>,
+    pub origin: OriginConfig,
+    pub ttls: CacheTtls,
+    pub stale: StalePolicy,
+    pub memory: CacheLimits,
+    pub snapshot: SnapshotConfig,
+    pub sqlite: SqliteConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: ([0, 0, 0, 0], 8080).into(),
+            layers: None,
+            signing_token: None,
+            origin: OriginConfig::default(),
+            ttls: CacheTtls::default(),
+            stale: StalePolicy::default(),
+            memory: CacheLimits::default(),
+            snapshot: SnapshotConfig::default(),
+            sqlite: SqliteConfig::default(),
+        }
+    }
+}
+
+// Flags override the environment, which overrides the configuration file
+#[derive(Parser, Debug)]
+#[command(version, about = "A caching proxy for the PluralKit API")]
+struct Args {
+    #[arg(short, long, env = "PLURALCACHE_CONFIG", help = "Path of the TOML configuration file")]
+    config: Option<PathBuf>,
+    #[arg(long, env = "PLURALCACHE_LISTEN", help = "Address to listen on")]
+    listen: Option<SocketAddr>,
+    #[arg(long, env = "PLURALCACHE_ORIGIN_URL", help = "Base URL of the PluralKit API")]
+    origin_url: Option<String>,
+    #[arg(long, env = "PLURALCACHE_ORIGIN_TIMEOUT", value_parser = parse_duration, help = "Timeout of requests to the origin")]
+    origin_timeout: Option<Duration>,
+    #[arg(long, env = "PLURALCACHE_LAYERS", value_delimiter = ',', help = "Cache layers to ask before the origin, in order")]
+    layers: Option<Vec<Layer>>,
+    #[arg(long, env = "PLURALCACHE_MAX_BYTES", help = "Memory limit of the in-memory cache")]
+    max_bytes: Option<usize>,
+    #[arg(long, env = "PLURALCACHE_SQLITE_PATH", help = "Path of the SQLite cache")]
+    sqlite_path: Option<PathBuf>,
+    #[arg(long, env = "PLURALCACHE_SNAPSHOT_PATH", help = "Path the in-memory cache is snapshotted to")]
+    snapshot_path: Option<PathBuf>,
+    #[arg(long, env = "PLURALCACHE_SNAPSHOT_INTERVAL", value_parser = parse_duration, help = "How often the snapshot is saved")]
+    snapshot_interval: Option<Duration>,
+    #[arg(long, env = "PLURALCACHE_SIGNING_TOKEN", hide_env_values = true, help = "Signing token of PluralKit dispatch events")]
+    signing_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => write!(f, "failed to read {}: {}", path.display(), error),
+            ConfigError::Parse(path, error) => write!(f, "failed to parse {}: {}", path.display(), error),
+            ConfigError::Invalid(problems) => {
+                write!(f, "{} problem(s) found", problems.len())?;
+                problems.iter().try_for_each(|problem| write!(f, "\n  {}", problem))
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let args = Args::parse();
+
+        let mut config = match &args.config {
+            Some(path) => Self::read(path)?,
+            None => Self::default(),
+        };
+
+        config.apply(args);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|error| ConfigError::Read(path.to_path_buf(), error))?;
+        toml::from_str(&contents).map_err(|error| ConfigError::Parse(path.to_path_buf(), error))
+    }
+
+    fn apply(&mut self, args: Args) {
+        if let Some(listen) = args.listen {
+            self.listen = listen;
+        }
+        if let Some(url) = args.origin_url {
+            self.origin.url = url;
+        }
+        if let Some(timeout) = args.origin_timeout {
+            self.origin.timeout = timeout;
+        }
+        if let Some(layers) = args.layers {
+            self.layers = Some(layers);
+        }
+        if let Some(max_bytes) = args.max_bytes {
+            self.memory.max_bytes = Some(max_bytes);
+        }
+        if let Some(path) = args.sqlite_path {
+            self.sqlite.path = Some(path);
+        }
+        if let Some(path) = args.snapshot_path {
+            self.snapshot.path = Some(path);
+        }
+        if let Some(interval) = args.snapshot_interval {
+            self.snapshot.interval = Some(interval);
+        }
+        if let Some(token) = args.signing_token {
+            self.signing_token = Some(token);
+        }
+    }
+
+    pub fn layers(&self) -> Vec<Layer> {
+        match &self.layers {
+            Some(layers) => layers.clone(),
+            None if self.sqlite.path.is_some() => vec![Layer::Memory, Layer::Sqlite],
+            None => vec![Layer::Memory],
+        }
+    }
+
+    // Every problem is reported at once rather than one per restart
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let layers = self.layers();
+        let mut check = |valid: bool, problem: &str| {
+            if !valid {
+                problems.push(problem.to_string());
+            }
+        };
+
+        let scheme = reqwest::Url::parse(&self.origin.url).map(|url| url.scheme().to_string());
+        check(matches!(scheme.as_deref(), Ok("http" | "https")), "origin.url must be an http or https URL");
+        check(!self.origin.timeout.is_zero(), "origin.timeout must be greater than 0");
+        check(!self.origin.connect_timeout.is_zero(), "origin.connect_timeout must be greater than 0");
+
+        let retry = &self.origin.retry;
+        check(retry.max_attempts >= 1, "origin.retry.max_attempts must be at least 1");
+        check(retry.max_timeout_attempts >= 1, "origin.retry.max_timeout_attempts must be at least 1");
+        check(retry.base_delay <= retry.max_delay, "origin.retry.base_delay can't be longer than origin.retry.max_delay");
+        check(retry.budget_ratio.is_finite() && retry.budget_ratio >= 0.0, "origin.retry.budget_ratio can't be negative");
+        check(retry.max_budget.is_finite() && retry.max_budget >= 0.0, "origin.retry.max_budget can't be negative");
+
+        check(self.origin.rate_limit.requests >= 1, "origin.rate_limit.requests must be at least 1");
+        check(!self.origin.rate_limit.period.is_zero(), "origin.rate_limit.period must be greater than 0");
+        check(self.origin.circuit_breaker.failure_threshold >= 1, "origin.circuit_breaker.failure_threshold must be at least 1");
+        check(self.origin.circuit_breaker.probe_successes >= 1, "origin.circuit_breaker.probe_successes must be at least 1");
+
+        check(self.memory.max_bytes != Some(0), "memory.max_bytes must be greater than 0");
+        check(self.memory.max_entries != Some(0), "memory.max_entries must be greater than 0");
+
+        let memory = layers.contains(&Layer::Memory);
+        let sqlite = layers.contains(&Layer::Sqlite);
+        check(layers.iter().enumerate().all(|(index, layer)| !layers[..index].contains(layer)), "layers can't contain the same layer twice");
+        check(!sqlite || self.sqlite.path.is_some(), "the sqlite layer requires sqlite.path");
+        check(sqlite || self.sqlite.path.is_none(), "sqlite.path is set but the sqlite layer isn't enabled");
+        check(!self.sqlite.prune_interval.is_zero(), "sqlite.prune_interval must be greater than 0");
+        check(memory || self.snapshot.path.is_none(), "snapshot.path requires the memory layer");
+        check(self.snapshot.interval.is_none() || self.snapshot.path.is_some(), "snapshot.interval requires snapshot.path");
+        check(self.snapshot.interval != Some(Duration::ZERO), "snapshot.interval must be greater than 0");
+        check(self.signing_token.as_ref().is_none_or(|token| !token.is_empty()), "signing_token can't be empty");
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
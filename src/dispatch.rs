@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+use crate::models::{Group, Member};
+use crate::privacy::PrivacyView;
+use crate::traits::notifier::{Entity, Notifier};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum EventType {
+    Ping,
+    UpdateSystem,
+    CreateMember,
+    UpdateMember,
+    DeleteMember,
+    CreateGroup,
+    UpdateGroup,
+    UpdateGroupMembers,
+    DeleteGroup,
+    LinkAccount,
+    UnlinkAccount,
+    UpdateSystemGuild,
+    UpdateMemberGuild,
+    CreateMessage,
+    CreateSwitch,
+    UpdateSwitch,
+    DeleteSwitch,
+    DeleteAllSwitches,
+    SuccessfulImport,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct DispatchEvent {
+    #[serde(rename = "type")]
+    kind: EventType,
+    signing_token: String,
+    system_id: Option<String>,
+    id: Option<String>,
+    #[serde(default)]
+    data: Value,
+}
+
+// Compares in constant time so the token can't be guessed byte by byte
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// Sends a notification to every cache layer
+macro_rules! broadcast {
+    ($self:ident, $notify:ident($($arg:expr),*)) => {
+        for notifier in &$self.notifiers {
+            notifier.lock().await.$notify($($arg),*).await;
+        }
+    };
+}
+
+// Applies PluralKit dispatch events to every cache layer so they are kept fresh by push rather than only by TTL
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+    notifiers: Vec<Arc<Mutex<dyn Notifier + Send + Sync>>>,
+    signing_token: String,
+}
+
+impl Dispatcher {
+    pub fn new(notifiers: Vec<Arc<Mutex<dyn Notifier + Send + Sync>>>, signing_token: String) -> Self {
+        Self {
+            notifiers,
+            signing_token,
+        }
+    }
+
+    // Asks each layer in turn, the first one to know the UUID answers for all of them
+    async fn resolve(&self, entity: Entity, uuid: Option<&str>) -> Option<String> {
+        let uuid = uuid?;
+
+        for notifier in &self.notifiers {
+            if let Some(id) = notifier.lock().await.resolve_uuid(entity, uuid).await {
+                return Some(id);
+            }
+        }
+
+        None
+    }
+
+    pub async fn handle(&self, event: DispatchEvent) -> StatusCode {
+        if !tokens_match(&event.signing_token, &self.signing_token) {
+            return StatusCode::UNAUTHORIZED;
+        }
+
+        let system = self.resolve(Entity::System, event.system_id.as_deref()).await;
+
+        match event.kind {
+            EventType::Ping | EventType::CreateMessage | EventType::Unknown => {}
+            EventType::UpdateSystem | EventType::UpdateSystemGuild => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_invalidated(Entity::System, system));
+                }
+            }
+            // The event's ID is the Discord account, which may have led to another system until now
+            EventType::LinkAccount | EventType::UnlinkAccount => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_invalidated(Entity::System, system));
+                }
+
+                if let Some(account) = &event.id {
+                    broadcast!(self, notify_alias_invalidated(Entity::System, account));
+                }
+            }
+            EventType::CreateMember => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_system_members_invalidated(system));
+                }
+
+                // Events carry the owner's view, only its public part can be shared with anonymous requests
+                if let Ok(member) = serde_json::from_value::<Member>(event.data) {
+                    broadcast!(self, notify_invalidated(Entity::Member, &member.id));
+
+                    if let Some(public) = member.is_owner_view().then(|| member.public_view()).flatten() {
+                        broadcast!(self, notify_member(None, &public));
+                    }
+                }
+            }
+            EventType::UpdateMember | EventType::UpdateMemberGuild => {
+                if let Some(member) = self.resolve(Entity::Member, event.id.as_deref()).await {
+                    broadcast!(self, notify_invalidated(Entity::Member, &member));
+                }
+            }
+            EventType::DeleteMember => {
+                if let Some(member) = self.resolve(Entity::Member, event.id.as_deref()).await {
+                    broadcast!(self, notify_deleted(Entity::Member, &member));
+                }
+            }
+            EventType::CreateGroup => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_system_groups_invalidated(system));
+                }
+
+                if let Ok(group) = serde_json::from_value::<Group>(event.data) {
+                    broadcast!(self, notify_invalidated(Entity::Group, &group.id));
+
+                    if let Some(public) = group.is_owner_view().then(|| group.public_view()).flatten() {
+                        broadcast!(self, notify_group(None, &public));
+                    }
+                }
+            }
+            EventType::UpdateGroup => {
+                if let Some(group) = self.resolve(Entity::Group, event.id.as_deref()).await {
+                    broadcast!(self, notify_invalidated(Entity::Group, &group));
+                }
+            }
+            EventType::UpdateGroupMembers => {
+                if let Some(group) = self.resolve(Entity::Group, event.id.as_deref()).await {
+                    broadcast!(self, notify_invalidated(Entity::Group, &group));
+                }
+
+                // Added members don't list the group yet, so their group lists are dropped explicitly
+                let members: Vec<String> = serde_json::from_value(event.data).unwrap_or_default();
+                for uuid in members {
+                    if let Some(member) = self.resolve(Entity::Member, Some(&uuid)).await {
+                        broadcast!(self, notify_member_groups_invalidated(&member));
+                    }
+                }
+            }
+            EventType::DeleteGroup => {
+                if let Some(group) = self.resolve(Entity::Group, event.id.as_deref()).await {
+                    broadcast!(self, notify_deleted(Entity::Group, &group));
+                }
+            }
+            EventType::CreateSwitch | EventType::UpdateSwitch | EventType::DeleteAllSwitches => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_system_switches_invalidated(system));
+                }
+            }
+            // Switch IDs are already UUIDs
+            EventType::DeleteSwitch => {
+                if let (Some(system), Some(switch)) = (&system, &event.id) {
+                    broadcast!(self, notify_switch_deleted(system, switch));
+                }
+            }
+            EventType::SuccessfulImport => {
+                if let Some(system) = &system {
+                    broadcast!(self, notify_invalidated(Entity::System, system));
+                    broadcast!(self, notify_system_members_invalidated(system));
+                    broadcast!(self, notify_system_groups_invalidated(system));
+                    broadcast!(self, notify_system_switches_invalidated(system));
+                }
+            }
+        }
+
+        StatusCode::OK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::implementations::in_memory_cache::{CacheLimits, CacheTtls, InMemoryCache};
+    use crate::models::System;
+    use crate::traits::provider::{Provider, ProviderResult};
+    use super::*;
+
+    const TOKEN: Option<&str> = Some("owner token");
+    const SIGNING_TOKEN: &str = "signing token";
+
+    fn system() -> System {
+        serde_json::from_value(json!({ "id": "sysid", "uuid": "00000000-0000-0000-0000-000000000001" })).unwrap()
+    }
+
+    fn event(kind: &str, id: &str) -> DispatchEvent {
+        serde_json::from_value(json!({
+            "type": kind,
+            "signing_token": SIGNING_TOKEN,
+            "system_id": system().uuid,
+            "id": id,
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unlinked_account_alias_is_forgotten() {
+        let cache = Arc::new(Mutex::new(InMemoryCache::new(CacheTtls::default(), CacheLimits::default())));
+        let dispatcher = Dispatcher::new(vec![cache.clone()], SIGNING_TOKEN.to_string());
+        cache.lock().await.notify_system(TOKEN, &system()).await;
+        cache.lock().await.notify_alias(TOKEN, Entity::System, "123456789", "sysid").await;
+        assert_eq!(cache.get_system(TOKEN, "123456789").await, ProviderResult::Ok(system()));
+
+        assert_eq!(dispatcher.handle(event("UNLINK_ACCOUNT", "123456789")).await, StatusCode::OK);
+
+        // Even once the system is fetched again, the account no longer leads to it
+        cache.lock().await.notify_system(TOKEN, &system()).await;
+        assert_eq!(cache.get_system(TOKEN, "123456789").await, ProviderResult::Miss);
+        assert_eq!(cache.get_system(TOKEN, "sysid").await, ProviderResult::Ok(system()));
+    }
+
+    #[tokio::test]
+    async fn events_with_the_wrong_signing_token_are_refused() {
+        let dispatcher = Dispatcher::new(Vec::new(), "another token".to_string());
+
+        assert_eq!(dispatcher.handle(event("UNLINK_ACCOUNT", "123456789")).await, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Serialize;
+use warp::http::StatusCode;
+use crate::traits::provider::{Failure, ProviderResult};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    System,
+    SystemSettings,
+    SystemGuildSettings,
+    SystemAutoproxy,
+    SystemMembers,
+    Member,
+    MemberGroups,
+    MemberGuildSettings,
+    SystemGroups,
+    Group,
+    GroupMembers,
+    SystemSwitches,
+    SystemFronters,
+    Switch,
+    Message,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PkError {
+    pub code: u32,
+    pub message: String,
+    // In milliseconds, only sent along with 429 errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    // Why the request failed, when the error didn't come from PluralKit itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl PkError {
+    fn new(code: u32, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            retry_after: None,
+            reason: None,
+        }
+    }
+}
+
+fn not_found(resource: Resource) -> (StatusCode, PkError) {
+    let error = match resource {
+        Resource::System
+        | Resource::SystemSettings
+        | Resource::SystemAutoproxy
+        | Resource::SystemMembers
+        | Resource::SystemGroups
+        | Resource::SystemSwitches
+        | Resource::SystemFronters => PkError::new(20001, "System not found."),
+        Resource::SystemGuildSettings => PkError::new(20009, "No system guild settings found for target guild."),
+        Resource::Member | Resource::MemberGroups => PkError::new(20002, "Member not found."),
+        Resource::MemberGuildSettings => PkError::new(20010, "No member guild settings found for target guild."),
+        Resource::Group | Resource::GroupMembers => PkError::new(20004, "Group not found."),
+        Resource::Switch => PkError::new(20007, "Switch not found."),
+        Resource::Message => PkError::new(20006, "Message not found."),
+    };
+
+    (StatusCode::NOT_FOUND, error)
+}
+
+fn unauthorized(resource: Resource) -> (StatusCode, PkError) {
+    match resource {
+        // PluralKit hides private entities behind their regular not found error
+        Resource::Member | Resource::MemberGroups | Resource::Group | Resource::Message => not_found(resource),
+        Resource::SystemMembers => (StatusCode::FORBIDDEN, PkError::new(30001, "Unauthorized to view member list")),
+        Resource::SystemGroups => (StatusCode::FORBIDDEN, PkError::new(30002, "Unauthorized to view group list")),
+        Resource::GroupMembers => (StatusCode::FORBIDDEN, PkError::new(30003, "Unauthorized to view group member list")),
+        Resource::SystemFronters => (StatusCode::FORBIDDEN, PkError::new(30004, "Unauthorized to view current fronters.")),
+        Resource::SystemSwitches => (StatusCode::FORBIDDEN, PkError::new(30005, "Unauthorized to view front history.")),
+        Resource::Switch => (
+            StatusCode::NOT_FOUND,
+            PkError::new(20008, "Switch not found, switch associated with different system, or unauthorized to view front history."),
+        ),
+        Resource::System
+        | Resource::SystemSettings
+        | Resource::SystemGuildSettings
+        | Resource::SystemAutoproxy
+        | Resource::MemberGuildSettings => (StatusCode::UNAUTHORIZED, PkError::new(0, "401: Missing or invalid Authorization header")),
+    }
+}
+
+pub fn error_for<T>(resource: Resource, result: &ProviderResult<T>) -> Option<(StatusCode, PkError)> {
+    match result {
+        ProviderResult::Ok(_) | ProviderResult::Cached(..) | ProviderResult::Stale(..) => None,
+        ProviderResult::NotFound => Some(not_found(resource)),
+        ProviderResult::Unauthorized => Some(unauthorized(resource)),
+        ProviderResult::RateLimited(retry_after) => Some((
+            StatusCode::TOO_MANY_REQUESTS,
+            PkError {
+                retry_after: Some(retry_after.as_millis() as u64),
+                ..PkError::new(0, "429: too many requests")
+            },
+        )),
+        // PluralKit's own errors are passed through as they are
+        ProviderResult::Failed(Failure::Origin { status, code, message }) => Some((
+            StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY),
+            PkError::new(*code, message),
+        )),
+        ProviderResult::Failed(failure) => Some((
+            StatusCode::BAD_GATEWAY,
+            PkError {
+                reason: Some(failure.to_string()),
+                ..PkError::new(0, "502: Bad gateway")
+            },
+        )),
+        ProviderResult::Miss => Some((StatusCode::BAD_GATEWAY, PkError::new(0, "502: Bad gateway"))),
+    }
+}
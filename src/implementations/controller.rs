@@ -1,408 +1,517 @@
 use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
+use serde::Deserialize;
 use tokio::sync::Mutex;
 use crate::models::{AutoproxySettings, Group, Member, MemberGuildSettings, Message, Switch, System, SystemGuildSettings, SystemSettings};
-use crate::traits::notifier::Notifier;
-use crate::traits::provider::{Provider, ProviderResult};
+use crate::traits::notifier::{fetched_ago, Entity, Notifier};
+use crate::single_flight::SingleFlight;
+use crate::traits::provider::{Failure, Provider, ProviderResult};
+
+// Walks the providers in order until one gives an authoritative answer, then notifies the caches above it
+macro_rules! resolve {
+    ($self:ident, $get:ident($token:ident, $($arg:ident),*), |$value:ident| $notify:ident($($notify_arg:expr),*) $(, $missing:ident($($missing_arg:expr),*))?) => {{
+        let prefix = if $self.serve_stale { "" } else { "revalidate:" };
+        let key = format!("{}{}{:?}", prefix, stringify!($get), ($token, $($arg,)*));
+
+        $self.in_flight.run(key, async {
+            let mut outcome = ProviderResult::Miss;
+            let mut fallback = None;
+
+            for provider in &$self.providers {
+                let result = provider.$get($token, $($arg),*).await;
+                let age = match &result {
+                    ProviderResult::Cached(_, age) => *age,
+                    _ => Duration::ZERO,
+                };
+
+                match result {
+                    // The layers above expire the data when the answering one would have, not a full TTL later
+                    ProviderResult::Ok($value) | ProviderResult::Cached($value, _) => {
+                        for notifier in $self.layers_above(provider) {
+                            let mut layer = notifier.lock().await;
+                            fetched_ago(age, layer.$notify($($notify_arg),*)).await;
+                        }
+
+                        return ProviderResult::Ok($value);
+                    }
+                    ProviderResult::NotFound => {
+                        $(
+                            for notifier in $self.layers_above(provider) {
+                                notifier.lock().await.$missing($($missing_arg),*).await;
+                            }
+                        )?
+
+                        return ProviderResult::NotFound;
+                    }
+                    ProviderResult::Stale(value, expired_for) if $self.serve_stale => {
+                        if expired_for <= $self.stale_policy.while_revalidate {
+                            let mut background = $self.clone();
+                            background.serve_stale = false;
+                            let $token = $token.detach();
+                            $(let $arg = $arg.detach();)*
+
+                            tokio::spawn(async move {
+                                let $token = $token.attach();
+                                $(let $arg = $arg.attach();)*
+                                background.$get($token, $($arg),*).await;
+                            });
+
+                            return ProviderResult::Stale(value, expired_for);
+                        }
+
+                        if fallback.is_none() && expired_for <= $self.stale_policy.if_error {
+                            fallback = Some(ProviderResult::Stale(value, expired_for));
+                        }
+                    }
+                    // Tokens are left out of the logs, as are requests failing fast while the circuit is open
+                    // since the breaker already logs when it opens and closes
+                    ProviderResult::Failed(failure) => {
+                        if failure != Failure::CircuitOpen {
+                            eprintln!("{}({}) failed: {}", stringify!($get), [$(format!("{:?}", $arg)),*].join(", "), failure);
+                        }
+
+                        outcome = outcome.or_worse(ProviderResult::Failed(failure));
+                    }
+                    result if result.is_authoritative() => return result,
+                    result => outcome = outcome.or_worse(result),
+                }
+            }
+
+            // Stale data is preferred over failing when nobody could give a fresh answer
+            fallback.unwrap_or(outcome)
+        }).await
+    }};
+}
 
-trait ProviderAndNotifier: Provider + Notifier {}
+// Request arguments are detached from the caller to refresh stale data in the background
+trait Detach {
+    type Owned: Attach + Send + 'static;
 
-pub(crate) struct Controller {
-    providers: Vec<Arc<Mutex<dyn Provider + Send + Sync>>>,
-    notifiers: Vec<Arc<Mutex<dyn Notifier + Send + Sync>>>,
+    fn detach(&self) -> Self::Owned;
 }
 
-impl Controller {
-    pub fn new() -> Self {
-        Self {
-            providers: Vec::new(),
-            notifiers: Vec::new(),
-        }
+trait Attach {
+    type Borrowed<'a> where Self: 'a;
+
+    fn attach(&self) -> Self::Borrowed<'_>;
+}
+
+impl Detach for &str {
+    type Owned = String;
+
+    fn detach(&self) -> String {
+        self.to_string()
     }
+}
 
-    pub fn add_provider(&mut self, provider: Arc<Mutex<dyn Provider + Send + Sync>>) {
-        self.providers.push(provider);
+impl Attach for String {
+    type Borrowed<'a> = &'a str;
+
+    fn attach(&self) -> &str {
+        self
     }
+}
 
-    pub fn add_notifier(&mut self, notifier: Arc<Mutex<dyn Notifier + Send + Sync>>) {
-        self.notifiers.push(notifier);
+impl Detach for Option<&str> {
+    type Owned = Option<String>;
+
+    fn detach(&self) -> Option<String> {
+        self.map(str::to_string)
     }
 }
 
-#[async_trait]
-impl Provider for Controller {
-    async fn get_system(&mut self, id: &str) -> ProviderResult<System> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system(id).await;
-            }
+impl Attach for Option<String> {
+    type Borrowed<'a> = Option<&'a str>;
 
-            if let ProviderResult::Ok(system) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system(&system).await;
-                }
+    fn attach(&self) -> Option<&str> {
+        self.as_deref()
+    }
+}
 
-                return ProviderResult::Ok(system);
-            }
+impl Detach for bool {
+    type Owned = bool;
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    fn detach(&self) -> bool {
+        *self
+    }
+}
 
-        }
-        ProviderResult::Failed
+impl Attach for bool {
+    type Borrowed<'a> = bool;
+
+    fn attach(&self) -> bool {
+        *self
     }
+}
 
-    async fn get_system_settings(&mut self, id: &str) -> ProviderResult<SystemSettings> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_settings(id).await;
-            }
+impl Detach for u64 {
+    type Owned = u64;
 
-            if let ProviderResult::Ok(system_settings) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_settings(&id, &system_settings).await;
-                }
+    fn detach(&self) -> u64 {
+        *self
+    }
+}
 
-                return ProviderResult::Ok(system_settings);
-            }
+impl Attach for u64 {
+    type Borrowed<'a> = u64;
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    fn attach(&self) -> u64 {
+        *self
+    }
+}
 
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct StalePolicy {
+    // Expired data younger than this is served right away while being refreshed in the background
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub while_revalidate: Duration,
+    // Expired data younger than this is served when no provider could give a fresh answer
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub if_error: Duration,
+}
+
+impl Default for StalePolicy {
+    fn default() -> Self {
+        Self {
+            while_revalidate: Duration::from_secs(30),
+            if_error: Duration::from_secs(60 * 60),
         }
-        ProviderResult::Failed
     }
+}
 
-    async fn get_system_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_guild_settings(id, guild).await;
-            }
+fn same_layer(provider: &Arc<dyn Provider + Send + Sync>, notifier: &Arc<Mutex<dyn Notifier + Send + Sync>>) -> bool {
+    std::ptr::addr_eq(Arc::as_ptr(provider), Arc::as_ptr(notifier))
+}
 
-            if let ProviderResult::Ok(system_guild_settings) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_guild_settings(&id, &guild, &system_guild_settings).await;
-                }
+#[derive(Clone)]
+pub(crate) struct Controller {
+    providers: Vec<Arc<dyn Provider + Send + Sync>>,
+    notifiers: Vec<Arc<Mutex<dyn Notifier + Send + Sync>>>,
+    in_flight: Arc<SingleFlight>,
+    stale_policy: StalePolicy,
+    serve_stale: bool,
+}
 
-                return ProviderResult::Ok(system_guild_settings);
-            }
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            providers: Vec::new(),
+            notifiers: Vec::new(),
+            in_flight: Arc::new(SingleFlight::default()),
+            stale_policy: StalePolicy::default(),
+            serve_stale: true,
+        }
+    }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    pub fn set_stale_policy(&mut self, stale_policy: StalePolicy) {
+        self.stale_policy = stale_policy;
+    }
 
-        }
-        ProviderResult::Failed
+    pub fn add_provider(&mut self, provider: Arc<dyn Provider + Send + Sync>) {
+        self.providers.push(provider);
     }
 
-    async fn get_system_autoproxy(&mut self, id: &str) -> ProviderResult<AutoproxySettings> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_autoproxy(id).await;
-            }
+    pub fn add_notifier(&mut self, notifier: Arc<Mutex<dyn Notifier + Send + Sync>>) {
+        self.notifiers.push(notifier);
+    }
 
-            if let ProviderResult::Ok(autoproxy_settings) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_autoproxy(&id, &autoproxy_settings).await;
-                }
+    pub fn notifiers(&self) -> Vec<Arc<Mutex<dyn Notifier + Send + Sync>>> {
+        self.notifiers.clone()
+    }
 
-                return ProviderResult::Ok(autoproxy_settings);
-            }
+    // A cache answering a request is left alone, or its entries would never expire. So are the ones below it,
+    // which would store the data as freshly fetched and keep refreshing each other without going back to the origin
+    fn layers_above(&self, provider: &Arc<dyn Provider + Send + Sync>) -> &[Arc<Mutex<dyn Notifier + Send + Sync>>] {
+        let layer = self.notifiers.iter().position(|notifier| same_layer(provider, notifier));
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        &self.notifiers[..layer.unwrap_or(self.notifiers.len())]
+    }
+
+    // Remembers which entity a UUID, Discord account or @me resolved to
+    async fn learn_alias<T>(&self, token: Option<&str>, entity: Entity, alias: &str, result: &ProviderResult<T>, id: impl Fn(&T) -> &str) {
+        let id = match result {
+            ProviderResult::Ok(value) | ProviderResult::Stale(value, _) => id(value),
+            _ => return,
+        };
+
+        if alias.eq_ignore_ascii_case(id) {
+            return;
+        }
 
+        for notifier in &self.notifiers {
+            notifier.lock().await.notify_alias(token, entity, alias, id).await;
         }
-        ProviderResult::Failed
     }
+}
 
-    async fn get_system_members(&mut self, id: &str) -> ProviderResult<Vec<Member>> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_members(id).await;
-            }
+#[async_trait]
+impl Provider for Controller {
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        let result = resolve!(self, get_system(token, id), |system| notify_system(token, &system), notify_not_found(token, Entity::System, id));
+        self.learn_alias(token, Entity::System, id, &result, |system| &system.id).await;
 
-            if let ProviderResult::Ok(members) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_members(&id, &members).await;
-                }
+        result
+    }
 
-                return ProviderResult::Ok(members);
-            }
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        resolve!(self, get_system_settings(token, id), |system_settings| notify_system_settings(token, id, &system_settings), notify_not_found(token, Entity::System, id))
+    }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        resolve!(self, get_system_guild_settings(token, id, guild), |system_guild_settings| notify_system_guild_settings(token, id, guild, &system_guild_settings))
+    }
 
-        }
-        ProviderResult::Failed
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        resolve!(self, get_system_autoproxy(token, id), |autoproxy_settings| notify_system_autoproxy(token, id, &autoproxy_settings), notify_not_found(token, Entity::System, id))
     }
 
-    async fn get_member(&mut self, id: &str) -> ProviderResult<Member> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_member(id).await;
-            }
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        resolve!(self, get_system_members(token, id), |members| notify_system_members(token, id, &members), notify_not_found(token, Entity::System, id))
+    }
 
-            if let ProviderResult::Ok(member) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_member(&member).await;
-                }
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        let result = resolve!(self, get_member(token, id), |member| notify_member(token, &member), notify_not_found(token, Entity::Member, id));
+        self.learn_alias(token, Entity::Member, id, &result, |member| &member.id).await;
 
-                return ProviderResult::Ok(member);
-            }
+        result
+    }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        resolve!(self, get_member_groups(token, id), |groups| notify_member_groups(token, id, &groups), notify_not_found(token, Entity::Member, id))
+    }
 
-        }
-        ProviderResult::Failed
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        resolve!(self, get_member_guild_settings(token, id, guild), |member_guild_settings| notify_member_guild_settings(token, id, guild, &member_guild_settings))
     }
 
-    async fn get_member_groups(&mut self, id: &str) -> ProviderResult<Vec<Group>> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_member_groups(id).await;
-            }
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        resolve!(self, get_system_groups(token, id, with_member), |groups| notify_system_groups(token, id, &groups), notify_not_found(token, Entity::System, id))
+    }
 
-            if let ProviderResult::Ok(groups) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_member_groups(&id, &groups).await;
-                }
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        let result = resolve!(self, get_group(token, id), |group| notify_group(token, &group), notify_not_found(token, Entity::Group, id));
+        self.learn_alias(token, Entity::Group, id, &result, |group| &group.id).await;
 
-                return ProviderResult::Ok(groups);
-            }
+        result
+    }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        resolve!(self, get_group_members(token, id), |members| notify_group_members(token, id, &members), notify_not_found(token, Entity::Group, id))
+    }
 
-        }
-        ProviderResult::Failed
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        resolve!(self, get_system_switches(token, id, before, limit), |switches| notify_system_switches(token, id, before, limit, &switches), notify_not_found(token, Entity::System, id))
     }
 
-    async fn get_member_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_member_guild_settings(id, guild).await;
-            }
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        resolve!(self, get_system_fronters(token, id), |switch| notify_system_fronters(token, id, &switch), notify_not_found(token, Entity::System, id))
+    }
 
-            if let ProviderResult::Ok(member_guild_settings) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_member_guild_settings(&id, &guild, &member_guild_settings).await;
-                }
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        resolve!(self, get_switch(token, id, switch_id), |switch| notify_switch(token, id, &switch))
+    }
 
-                return ProviderResult::Ok(member_guild_settings);
-            }
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        resolve!(self, get_message(token, id), |message| notify_message(token, &message), notify_not_found(token, Entity::Message, id))
+    }
+}
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use serde_json::json;
+    use crate::implementations::in_memory_cache::{CacheLimits, CacheTtls, InMemoryCache};
+    use super::*;
+
+    fn system() -> System {
+        serde_json::from_value(json!({ "id": "sysid", "uuid": "00000000-0000-0000-0000-000000000001" })).unwrap()
+    }
+
+    // Answers every system request with the same result after a delay and counts them, anything else misses
+    struct Stub {
+        system: ProviderResult<System>,
+        delay: Duration,
+        calls: AtomicUsize,
+    }
+
+    impl Stub {
+        fn new(system: ProviderResult<System>) -> Arc<Self> {
+            Self::delayed(system, Duration::ZERO)
+        }
+
+        fn delayed(system: ProviderResult<System>, delay: Duration) -> Arc<Self> {
+            Arc::new(Self {
+                system,
+                delay,
+                calls: AtomicUsize::new(0),
+            })
+        }
 
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
         }
-        ProviderResult::Failed
     }
 
-    async fn get_system_groups(&mut self, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_groups(id, with_member).await;
-            }
+    #[async_trait]
+    impl Provider for Stub {
+        async fn get_system(&self, _token: Option<&str>, _id: &str) -> ProviderResult<System> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
 
-            if let ProviderResult::Ok(groups) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_groups(&id, &groups).await;
-                }
+            self.system.clone()
+        }
 
-                return ProviderResult::Ok(groups);
-            }
+        async fn get_system_settings(&self, _token: Option<&str>, _id: &str) -> ProviderResult<SystemSettings> {
+            ProviderResult::Miss
+        }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        async fn get_system_guild_settings(&self, _token: Option<&str>, _id: &str, _guild: &str) -> ProviderResult<SystemGuildSettings> {
+            ProviderResult::Miss
+        }
 
+        async fn get_system_autoproxy(&self, _token: Option<&str>, _id: &str) -> ProviderResult<AutoproxySettings> {
+            ProviderResult::Miss
         }
-        ProviderResult::Failed
-    }
 
-    async fn get_group(&mut self, id: &str) -> ProviderResult<Group> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_group(id).await;
-            }
+        async fn get_system_members(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Vec<Member>> {
+            ProviderResult::Miss
+        }
 
-            if let ProviderResult::Ok(group) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_group(&group).await;
-                }
+        async fn get_member(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Member> {
+            ProviderResult::Miss
+        }
 
-                return ProviderResult::Ok(group);
-            }
+        async fn get_member_groups(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Vec<Group>> {
+            ProviderResult::Miss
+        }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        async fn get_member_guild_settings(&self, _token: Option<&str>, _id: &str, _guild: &str) -> ProviderResult<MemberGuildSettings> {
+            ProviderResult::Miss
+        }
 
+        async fn get_system_groups(&self, _token: Option<&str>, _id: &str, _with_member: bool) -> ProviderResult<Vec<Group>> {
+            ProviderResult::Miss
         }
-        ProviderResult::Failed
-    }
 
-    async fn get_group_members(&mut self, id: &str) -> ProviderResult<Vec<Member>> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_group_members(id).await;
-            }
+        async fn get_group(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Group> {
+            ProviderResult::Miss
+        }
 
-            if let ProviderResult::Ok(members) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_group_members(&id, &members).await;
-                }
+        async fn get_group_members(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Vec<Member>> {
+            ProviderResult::Miss
+        }
 
-                return ProviderResult::Ok(members);
-            }
+        async fn get_system_switches(&self, _token: Option<&str>, _id: &str, _before: &str, _limit: u64) -> ProviderResult<Vec<Switch>> {
+            ProviderResult::Miss
+        }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        async fn get_system_fronters(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Switch> {
+            ProviderResult::Miss
+        }
 
+        async fn get_switch(&self, _token: Option<&str>, _id: &str, _switch_id: &str) -> ProviderResult<Switch> {
+            ProviderResult::Miss
+        }
+
+        async fn get_message(&self, _token: Option<&str>, _id: &str) -> ProviderResult<Message> {
+            ProviderResult::Miss
         }
-        ProviderResult::Failed
     }
 
-    async fn get_system_switches(&mut self, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_switches(id, before, limit).await;
-            }
+    #[tokio::test]
+    async fn layers_above_expire_data_when_the_answering_layer_would() {
+        let ttls = CacheTtls { systems: Duration::from_secs(60), ..CacheTtls::default() };
+        let memory = Arc::new(Mutex::new(InMemoryCache::new(ttls, CacheLimits::default())));
+        // Fetched two minutes ago, still fresh for a lower layer with a longer TTL
+        let persistent = Stub::new(ProviderResult::Cached(system(), Duration::from_secs(120)));
 
-            if let ProviderResult::Ok(switches) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_switches(&id, &switches).await;
-                }
+        let mut controller = Controller::new();
+        controller.add_provider(memory.clone());
+        controller.add_notifier(memory.clone());
+        controller.add_provider(persistent);
 
-                return ProviderResult::Ok(switches);
-            }
+        assert_eq!(controller.get_system(None, "sysid").await, ProviderResult::Ok(system()));
+        assert!(matches!(memory.get_system(None, "sysid").await, ProviderResult::Stale(_, expired_for) if expired_for >= Duration::from_secs(60)));
+    }
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+    #[tokio::test]
+    async fn data_fetched_from_the_origin_is_stored_fresh() {
+        let memory = Arc::new(Mutex::new(InMemoryCache::new(CacheTtls::default(), CacheLimits::default())));
+        let origin = Stub::new(ProviderResult::Ok(system()));
 
-        }
-        ProviderResult::Failed
+        let mut controller = Controller::new();
+        controller.add_provider(memory.clone());
+        controller.add_notifier(memory.clone());
+        controller.add_provider(origin);
+
+        assert_eq!(controller.get_system(None, "sysid").await, ProviderResult::Ok(system()));
+        assert_eq!(memory.get_system(None, "sysid").await, ProviderResult::Ok(system()));
     }
 
-    async fn get_system_active_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_system_active_switch(id, switch_id).await;
-            }
+    #[tokio::test]
+    async fn concurrent_requests_share_a_single_fetch() {
+        let origin = Stub::delayed(ProviderResult::Ok(system()), Duration::from_millis(50));
 
-            if let ProviderResult::Ok(switch) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_system_active_switch(&id, &switch).await;
-                }
+        let mut controller = Controller::new();
+        controller.add_provider(origin.clone());
 
-                return ProviderResult::Ok(switch);
-            }
+        let (first, second) = tokio::join!(controller.get_system(None, "sysid"), controller.get_system(None, "sysid"));
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        assert_eq!(first, ProviderResult::Ok(system()));
+        assert_eq!(second, ProviderResult::Ok(system()));
+        assert_eq!(origin.calls(), 1);
 
-        }
-        ProviderResult::Failed
+        controller.get_system(Some("token"), "sysid").await;
+
+        assert_eq!(origin.calls(), 2);
     }
 
-    async fn get_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_switch(id, switch_id).await;
-            }
+    fn layered(cache: &Arc<Stub>, origin: &Arc<Stub>) -> Controller {
+        let mut controller = Controller::new();
+        controller.add_provider(cache.clone());
+        controller.add_provider(origin.clone());
 
-            if let ProviderResult::Ok(switch) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_switch(&id, &switch).await;
-                }
+        controller
+    }
 
-                return ProviderResult::Ok(switch);
-            }
+    #[tokio::test]
+    async fn recently_expired_data_is_served_while_revalidating() {
+        let expired_for = Duration::from_secs(10);
+        let cache = Stub::new(ProviderResult::Stale(system(), expired_for));
+        let origin = Stub::new(ProviderResult::Ok(system()));
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        assert_eq!(layered(&cache, &origin).get_system(None, "sysid").await, ProviderResult::Stale(system(), expired_for));
 
-        }
-        ProviderResult::Failed
+        // The refresh runs once the answer has been sent
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(origin.calls(), 1);
     }
 
-    async fn get_message(&mut self, id: &str) -> ProviderResult<Message> {
-        for provider in &mut self.providers {
-            let result;
-            {
-                let mut provider = provider.lock().await;
-                result = provider.get_message(id).await;
-            }
+    #[tokio::test]
+    async fn older_expired_data_is_served_when_the_origin_fails() {
+        let expired_for = Duration::from_secs(10 * 60);
+        let cache = Stub::new(ProviderResult::Stale(system(), expired_for));
+        let origin = Stub::new(ProviderResult::Failed(Failure::Timeout));
 
-            if let ProviderResult::Ok(message) = result {
-                for notifier in &mut self.notifiers {
-                    let mut notifier = notifier.lock().await;
-                    notifier.notify_message(&message).await;
-                }
+        assert_eq!(layered(&cache, &origin).get_system(None, "sysid").await, ProviderResult::Stale(system(), expired_for));
+        assert_eq!(origin.calls(), 1);
+    }
 
-                return ProviderResult::Ok(message);
-            }
+    #[tokio::test]
+    async fn older_expired_data_waits_for_fresh_data() {
+        let cache = Stub::new(ProviderResult::Stale(system(), Duration::from_secs(10 * 60)));
+        let origin = Stub::new(ProviderResult::Ok(system()));
 
-            if [ProviderResult::NotFound, ProviderResult::NotImplemented].contains(&result) {
-                return result;
-            }
+        assert_eq!(layered(&cache, &origin).get_system(None, "sysid").await, ProviderResult::Ok(system()));
+    }
 
-        }
-        ProviderResult::Failed
+    #[tokio::test]
+    async fn data_expired_for_too_long_is_never_served() {
+        let cache = Stub::new(ProviderResult::Stale(system(), Duration::from_secs(2 * 60 * 60)));
+        let origin = Stub::new(ProviderResult::Failed(Failure::Timeout));
+
+        assert_eq!(layered(&cache, &origin).get_system(None, "sysid").await, ProviderResult::Failed(Failure::Timeout));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+use crate::implementations::hash_token;
+use crate::models::{Group, Member, Message, System};
+use crate::traits::notifier::Entity;
+use super::entry::{forget, store, FreshMap};
+use super::{CachePartition, InMemoryCache};
+
+const ME: &str = "@me";
+
+// PluralKit accepts UUIDs, Discord accounts and @me in place of IDs, in any case.
+// Every form is resolved to the lowercase short ID the cache is keyed by
+impl InMemoryCache {
+    pub(super) fn canonical(&self, token: Option<&str>, entity: Entity, id: &str) -> String {
+        let key = (entity, id.to_lowercase());
+        let ttl = self.ttls.aliases;
+
+        // @me depends on the token, every other alias holds for everyone
+        let alias = self.partition(token)
+            .and_then(|partition| partition.aliases.fresh(&key, ttl))
+            .or_else(|| {
+                if key.1 == ME {
+                    return None;
+                }
+
+                self.partitions.values().find_map(|partition| partition.aliases.fresh(&key, ttl))
+            });
+
+        alias.cloned().unwrap_or(key.1)
+    }
+
+    // @me is only forgotten for the token it was resolved with, every other alias for everyone
+    pub(super) fn forget_alias(&mut self, token: Option<&str>, entity: Entity, alias: &str) {
+        let key = (entity, alias.to_lowercase());
+
+        if key.1 == ME {
+            if let Some(partition) = self.partitions.get_mut(&token.map(hash_token)) {
+                forget(&mut partition.aliases, &mut partition.usage, &key);
+            }
+
+            return;
+        }
+
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.aliases, &mut partition.usage, &key);
+        }
+    }
+
+    // UUID aliases hold for every token, whichever partition learnt them. Every entity stored teaches its own,
+    // embedded ones included, and expired ones are still good as an entity keeps its UUID
+    pub(super) fn find_uuid(&self, entity: Entity, uuid: &str) -> Option<String> {
+        let key = (entity, uuid.to_lowercase());
+
+        self.partitions.values()
+            .find_map(|partition| partition.aliases.get(&key))
+            .map(|entry| entry.peek().clone())
+    }
+}
+
+impl CachePartition {
+    pub(super) fn learn_alias(&mut self, entity: Entity, alias: &str, id: &str) {
+        let alias = alias.to_lowercase();
+
+        if alias != id {
+            store(&mut self.aliases, &mut self.usage, (entity, alias), id.to_string());
+        }
+    }
+
+    pub(super) fn learn_system(&mut self, system: &System) {
+        self.learn_alias(Entity::System, &system.uuid, &system.id);
+    }
+
+    pub(super) fn learn_members(&mut self, members: &[Member]) {
+        for member in members {
+            self.learn_alias(Entity::Member, &member.uuid, &member.id);
+        }
+    }
+
+    pub(super) fn learn_groups(&mut self, groups: &[Group]) {
+        for group in groups {
+            self.learn_alias(Entity::Group, &group.uuid, &group.id);
+            self.learn_members(group.members.as_deref().unwrap_or_default());
+        }
+    }
+
+    // The sender of a proxied message is one of the Discord accounts linked to its system
+    pub(super) fn learn_message(&mut self, message: &Message) {
+        self.learn_members(message.member.as_slice());
+
+        if let Some(system) = &message.system {
+            self.learn_system(system);
+            self.learn_alias(Entity::System, &message.sender, &system.id);
+        }
+    }
+}
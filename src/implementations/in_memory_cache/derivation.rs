@@ -0,0 +1,104 @@
+use crate::models::{Group, Member, MemberOrId};
+use crate::traits::notifier::Entity;
+use super::entry::FreshMap;
+use super::index::Container;
+use super::{contains_group, contains_member, CachePartition, CacheTtls};
+
+fn find_member<'a>(members: &'a [Member], id: &str) -> Option<&'a Member> {
+    members.iter().find(|member| member.id == id)
+}
+
+fn find_embedded_member<'a>(groups: &'a [Group], id: &str) -> Option<&'a Member> {
+    groups.iter().find_map(|group| find_member(group.members.as_deref()?, id))
+}
+
+fn find_group<'a>(groups: &'a [Group], id: &str) -> Option<&'a Group> {
+    groups.iter().find(|group| group.id == id)
+}
+
+// Answers computed from data cached for other requests. Embedded copies are judged by the TTL of the entity
+// they describe, and lists are only derived once every piece they are made of is known to be complete.
+// Entries are only looked through here, they don't count as accessed for eviction
+impl CachePartition {
+    fn fresh_groups<'a>(&'a self, id: &'a str, ttls: &CacheTtls) -> impl Iterator<Item = &'a Group> {
+        let ttl = ttls.groups;
+
+        self.groups.peek_fresh(id, ttl).into_iter().chain(self.containers(Entity::Group, id).filter_map(move |(container, key)| match container {
+            Container::SystemGroups => find_group(self.system_groups.peek_fresh(key, ttl)?, id),
+            Container::MemberGroups => find_group(self.member_groups.peek_fresh(key, ttl)?, id),
+            _ => None,
+        }))
+    }
+
+    // A member list is complete when fetched on its own or embedded in a group fetched with its members
+    fn known_group_members<'a>(&'a self, group: &'a str, ttls: &CacheTtls) -> Option<&'a [Member]> {
+        self.group_members.peek_fresh(group, ttls.members).map(Vec::as_slice).or_else(|| {
+            self.fresh_groups(group, ttls).find_map(|candidate| candidate.members.as_deref())
+        })
+    }
+
+    pub(super) fn derive_member(&self, id: &str, ttls: &CacheTtls) -> Option<Member> {
+        let ttl = ttls.members;
+
+        self.containers(Entity::Member, id)
+            .find_map(|(container, key)| match container {
+                Container::SystemMembers => find_member(self.system_members.peek_fresh(key, ttl)?, id),
+                Container::GroupMembers => find_member(self.group_members.peek_fresh(key, ttl)?, id),
+                Container::Group => find_member(self.groups.peek_fresh(key, ttls.groups)?.members.as_deref()?, id),
+                Container::SystemGroups => find_embedded_member(self.system_groups.peek_fresh(key, ttls.groups)?, id),
+                Container::MemberGroups => find_embedded_member(self.member_groups.peek_fresh(key, ttls.groups)?, id),
+                Container::SystemFronters => self.system_fronters.peek_fresh(key, ttl)?.members.iter().find_map(|member| match member {
+                    MemberOrId::Member(member) if member.id == id => Some(member),
+                    _ => None,
+                }),
+                Container::Message => self.messages.peek_fresh(key, ttl)?.member.as_ref().filter(|member| member.id == id),
+            })
+            .cloned()
+    }
+
+    pub(super) fn derive_group(&self, id: &str, ttls: &CacheTtls) -> Option<Group> {
+        self.fresh_groups(id, ttls)
+            .next()
+            .map(|group| Group { members: None, ..group.clone() })
+    }
+
+    // Complete once the member's system, its whole group list and every group's members are known
+    pub(super) fn derive_member_groups(&self, member: &str, ttls: &CacheTtls) -> Option<Vec<Group>> {
+        let system = self.containers(Entity::Member, member)
+            .filter(|(container, _)| *container == Container::SystemMembers)
+            .map(|(_, system)| system)
+            .find(|system| self.system_members.peek_fresh(*system, ttls.members).is_some_and(|members| contains_member(members, member)))?;
+        let groups = self.system_groups.peek_fresh(system, ttls.groups)?;
+
+        let mut member_groups = Vec::new();
+        for group in groups {
+            if contains_member(self.known_group_members(&group.id, ttls)?, member) {
+                member_groups.push(Group { members: None, ..group.clone() });
+            }
+        }
+
+        Some(member_groups)
+    }
+
+    // Complete when embedded in a group, or once the group's system, its whole member list and every member's groups are known
+    pub(super) fn derive_group_members(&self, group: &str, ttls: &CacheTtls) -> Option<Vec<Member>> {
+        if let Some(members) = self.known_group_members(group, ttls) {
+            return Some(members.to_vec());
+        }
+
+        let system = self.containers(Entity::Group, group)
+            .filter(|(container, _)| *container == Container::SystemGroups)
+            .map(|(_, system)| system)
+            .find(|system| self.system_groups.peek_fresh(*system, ttls.groups).is_some_and(|groups| contains_group(groups, group)))?;
+        let members = self.system_members.peek_fresh(system, ttls.members)?;
+
+        let mut group_members = Vec::new();
+        for member in members {
+            if contains_group(self.member_groups.peek_fresh(&member.id, ttls.groups)?, group) {
+                group_members.push(member.clone());
+            }
+        }
+
+        Some(group_members)
+    }
+}
@@ -0,0 +1,315 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::traits::notifier::notified_age;
+
+// Monotonic counter used to order accesses across every map of the cache
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EvictionPolicy {
+    Lru,
+    Lfu,
+}
+
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Approximates the heap footprint of a value by the length of its JSON representation
+fn approximate_size<T: Serialize>(value: &T) -> usize {
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+pub(super) struct CacheEntry<T> {
+    value: T,
+    fetched_at: Instant,
+    size: usize,
+    last_used: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl<T: Clone> Clone for CacheEntry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            fetched_at: self.fetched_at,
+            size: self.size,
+            last_used: AtomicU64::new(self.last_used.load(Ordering::Relaxed)),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T: Serialize> CacheEntry<T> {
+    fn measure<K>(value: &T) -> usize {
+        size_of::<K>() + size_of::<Self>() + approximate_size(value)
+    }
+
+    pub fn new<K>(value: T) -> Self {
+        let size = Self::measure::<K>(&value);
+
+        Self {
+            value,
+            fetched_at: Instant::now().checked_sub(notified_age()).unwrap_or_else(Instant::now),
+            size,
+            last_used: AtomicU64::new(tick()),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    fn remeasure<K>(&mut self, usage: &mut Usage) {
+        let size = Self::measure::<K>(&self.value);
+        usage.sub(Usage { bytes: self.size, entries: 0 });
+        usage.add(Usage { bytes: size, entries: 0 });
+        self.size = size;
+    }
+}
+
+impl<T> CacheEntry<T> {
+    // Expired entries are treated as misses so the request falls through to the next provider
+    pub fn get(&self, ttl: Duration) -> Option<&T> {
+        if self.is_expired(ttl) {
+            return None;
+        }
+
+        self.last_used.store(tick(), Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(&self.value)
+    }
+
+    // Expired entries are kept until evicted so they can still be served as stale data
+    pub fn get_stale(&self, ttl: Duration) -> Option<(&T, Duration)> {
+        let expired_for = self.fetched_at.elapsed().checked_sub(ttl)?;
+
+        self.last_used.store(tick(), Ordering::Relaxed);
+        Some((&self.value, expired_for))
+    }
+
+    // Reads the value regardless of its age without counting as an access
+    pub fn peek(&self) -> &T {
+        &self.value
+    }
+
+    // Freshness check for entries only looked through, which shouldn't count as accessed
+    pub fn peek_fresh(&self, ttl: Duration) -> Option<&T> {
+        (!self.is_expired(ttl)).then_some(&self.value)
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.fetched_at.elapsed() >= ttl
+    }
+
+    fn rank(&self, policy: EvictionPolicy, ttl: Duration) -> u128 {
+        if self.is_expired(ttl) {
+            return 0;
+        }
+
+        let last_used = self.last_used.load(Ordering::Relaxed) as u128;
+
+        match policy {
+            EvictionPolicy::Lru => last_used,
+            // Ties between equally used entries are broken by recency
+            EvictionPolicy::Lfu => ((self.hits.load(Ordering::Relaxed) as u128) << 64) | last_used,
+        }
+    }
+}
+
+pub(super) trait FreshMap<K, V> {
+    fn fresh<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<&V> where K: Borrow<Q>;
+    fn stale<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<(&V, Duration)> where K: Borrow<Q>;
+    fn peek_fresh<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<&V> where K: Borrow<Q>;
+}
+
+impl<K: Hash + Eq, V> FreshMap<K, V> for HashMap<K, CacheEntry<V>> {
+    fn fresh<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<&V> where K: Borrow<Q> {
+        self.get(key).and_then(|entry| entry.get(ttl))
+    }
+
+    fn stale<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<(&V, Duration)> where K: Borrow<Q> {
+        self.get(key).and_then(|entry| entry.get_stale(ttl))
+    }
+
+    fn peek_fresh<Q: Hash + Eq + ?Sized>(&self, key: &Q, ttl: Duration) -> Option<&V> where K: Borrow<Q> {
+        self.get(key).and_then(|entry| entry.peek_fresh(ttl))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct Usage {
+    pub bytes: usize,
+    pub entries: usize,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: Usage) {
+        self.bytes += other.bytes;
+        self.entries += other.entries;
+    }
+
+    pub fn sub(&mut self, other: Usage) {
+        self.bytes -= other.bytes;
+        self.entries -= other.entries;
+    }
+}
+
+// Type-erased view over the entry maps so eviction can sweep all of them at once
+pub(super) trait EntryMap {
+    fn usage(&self) -> Usage;
+    fn ranks(&self, policy: EvictionPolicy, ttl: Duration, out: &mut Vec<(u128, usize)>);
+    fn evict_below(&mut self, policy: EvictionPolicy, ttl: Duration, threshold: u128) -> Usage;
+}
+
+impl<K: Hash + Eq, V> EntryMap for HashMap<K, CacheEntry<V>> {
+    fn usage(&self) -> Usage {
+        Usage {
+            bytes: self.values().map(|entry| entry.size).sum(),
+            entries: self.len(),
+        }
+    }
+
+    fn ranks(&self, policy: EvictionPolicy, ttl: Duration, out: &mut Vec<(u128, usize)>) {
+        out.extend(self.values().map(|entry| (entry.rank(policy, ttl), entry.size)));
+    }
+
+    fn evict_below(&mut self, policy: EvictionPolicy, ttl: Duration, threshold: u128) -> Usage {
+        let mut evicted = Usage::default();
+
+        self.retain(|_, entry| {
+            if entry.rank(policy, ttl) < threshold {
+                evicted.add(Usage { bytes: entry.size, entries: 1 });
+                false
+            } else {
+                true
+            }
+        });
+
+        evicted
+    }
+}
+
+pub(super) fn store<K: Hash + Eq, V: Serialize>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, key: K, value: V) {
+    let entry = CacheEntry::new::<K>(value);
+    usage.add(Usage { bytes: entry.size, entries: 1 });
+
+    if let Some(previous) = map.insert(key, entry) {
+        usage.sub(Usage { bytes: previous.size, entries: 1 });
+    }
+}
+
+pub(super) fn forget<K: Hash + Eq + Borrow<Q>, V, Q: Hash + Eq + ?Sized>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, key: &Q) -> Option<V> {
+    let previous = map.remove(key)?;
+    usage.sub(Usage { bytes: previous.size, entries: 1 });
+
+    Some(previous.value)
+}
+
+pub(super) fn forget_where<K: Hash + Eq, V>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, predicate: impl Fn(&K, &V) -> bool) {
+    map.retain(|key, entry| {
+        if predicate(key, &entry.value) {
+            usage.sub(Usage { bytes: entry.size, entries: 1 });
+            false
+        } else {
+            true
+        }
+    });
+}
+
+// Only looks at the given keys, for callers which know where matching entries can be
+pub(super) fn forget_keys_where<K: Hash + Eq, V>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, keys: impl IntoIterator<Item = K>, predicate: impl Fn(&K, &V) -> bool) {
+    for key in keys {
+        if map.get(&key).is_some_and(|entry| predicate(&key, &entry.value)) {
+            forget(map, usage, &key);
+        }
+    }
+}
+
+// Edits values in place without refreshing them, `update` returns whether the value changed
+pub(super) fn update_where<K: Hash + Eq, V: Serialize>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, mut update: impl FnMut(&K, &mut V) -> bool) {
+    for (key, entry) in map.iter_mut() {
+        if update(key, &mut entry.value) {
+            entry.remeasure::<K>(usage);
+        }
+    }
+}
+
+pub(super) fn update_at<K: Hash + Eq + Borrow<Q>, V: Serialize, Q: Hash + Eq + ?Sized>(map: &mut HashMap<K, CacheEntry<V>>, usage: &mut Usage, key: &Q, update: impl FnOnce(&mut V) -> bool) {
+    if let Some(entry) = map.get_mut(key) {
+        if update(&mut entry.value) {
+            entry.remeasure::<K>(usage);
+        }
+    }
+}
+
+// Entries are written with the wall clock time they were fetched at, so the time spent on disk counts towards their age
+#[derive(Serialize, Deserialize)]
+struct StoredEntry<T> {
+    value: T,
+    fetched_at: DateTime<Utc>,
+    size: usize,
+    hits: u64,
+}
+
+impl<T> CacheEntry<T> {
+    fn stored(&self) -> StoredEntry<&T> {
+        let age = chrono::Duration::from_std(self.fetched_at.elapsed()).unwrap_or_default();
+
+        StoredEntry {
+            value: &self.value,
+            fetched_at: Utc::now() - age,
+            size: self.size,
+            hits: self.hits.load(Ordering::Relaxed),
+        }
+    }
+
+    // Entries older than the monotonic clock itself can't be represented and are dropped
+    fn restored(stored: StoredEntry<T>) -> Option<Self> {
+        let age = (Utc::now() - stored.fetched_at).to_std().unwrap_or_default();
+
+        Some(Self {
+            value: stored.value,
+            fetched_at: Instant::now().checked_sub(age)?,
+            size: stored.size,
+            last_used: AtomicU64::new(tick()),
+            hits: AtomicU64::new(stored.hits),
+        })
+    }
+}
+
+// Serializes entry maps as lists of pairs, as most keys can't be JSON object keys
+pub(super) mod stored_entries {
+    use super::*;
+
+    pub fn serialize<S: Serializer, K: Serialize, V: Serialize>(map: &HashMap<K, CacheEntry<V>>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter().map(|(key, entry)| (key, entry.stored())))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, K: DeserializeOwned + Hash + Eq, V: DeserializeOwned>(deserializer: D) -> Result<HashMap<K, CacheEntry<V>>, D::Error> {
+        let entries: Vec<(K, StoredEntry<V>)> = Vec::deserialize(deserializer)?;
+
+        Ok(entries.into_iter().filter_map(|(key, stored)| Some((key, CacheEntry::restored(stored)?))).collect())
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use crate::models::{Group, Member, MemberOrId};
+use crate::traits::notifier::Entity;
+use super::CachePartition;
+
+// Entries which embed copies of other entities
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum Container {
+    SystemMembers,
+    GroupMembers,
+    Group,
+    SystemGroups,
+    MemberGroups,
+    SystemFronters,
+    Message,
+}
+
+const CONTAINERS: [Container; 7] = [
+    Container::SystemMembers,
+    Container::GroupMembers,
+    Container::Group,
+    Container::SystemGroups,
+    Container::MemberGroups,
+    Container::SystemFronters,
+    Container::Message,
+];
+
+// Where each entity is embedded, so updates and derivations don't have to go through every list.
+// Containers are added when stored and only dropped once they are gone from the cache, one listed here
+// may have been replaced by a value which no longer embeds the entity
+#[derive(Clone, Default)]
+pub(super) struct EmbedIndex(HashMap<(Entity, String), HashSet<(Container, String)>>);
+
+fn push_members<'a>(embedded: &mut Vec<(Entity, String)>, members: impl IntoIterator<Item = &'a Member>) {
+    embedded.extend(members.into_iter().map(|member| (Entity::Member, member.id.clone())));
+}
+
+fn push_groups(embedded: &mut Vec<(Entity, String)>, groups: &[Group]) {
+    for group in groups {
+        embedded.push((Entity::Group, group.id.clone()));
+        push_members(embedded, group.members.iter().flatten());
+    }
+}
+
+impl CachePartition {
+    fn embedded_in(&self, container: Container, key: &str) -> Vec<(Entity, String)> {
+        let mut embedded = Vec::new();
+
+        match container {
+            Container::SystemMembers => push_members(&mut embedded, self.system_members.get(key).into_iter().flat_map(|entry| entry.peek())),
+            Container::GroupMembers => push_members(&mut embedded, self.group_members.get(key).into_iter().flat_map(|entry| entry.peek())),
+            Container::Group => push_members(&mut embedded, self.groups.get(key).into_iter().flat_map(|entry| entry.peek().members.iter().flatten())),
+            Container::SystemGroups => push_groups(&mut embedded, self.system_groups.get(key).map(|entry| entry.peek().as_slice()).unwrap_or_default()),
+            Container::MemberGroups => push_groups(&mut embedded, self.member_groups.get(key).map(|entry| entry.peek().as_slice()).unwrap_or_default()),
+            Container::SystemFronters => push_members(&mut embedded, self.system_fronters.get(key).into_iter().flat_map(|entry| {
+                entry.peek().members.iter().filter_map(|member| match member {
+                    MemberOrId::Member(member) => Some(member),
+                    MemberOrId::Id(_) => None,
+                })
+            })),
+            Container::Message => {
+                if let Some(entry) = self.messages.get(key) {
+                    let message = entry.peek();
+
+                    push_members(&mut embedded, message.member.iter());
+                    embedded.extend(message.system.iter().map(|system| (Entity::System, system.id.clone())));
+                }
+            }
+        }
+
+        embedded
+    }
+
+    fn container_keys(&self, container: Container) -> Vec<String> {
+        match container {
+            Container::SystemMembers => self.system_members.keys().cloned().collect(),
+            Container::GroupMembers => self.group_members.keys().cloned().collect(),
+            Container::Group => self.groups.keys().cloned().collect(),
+            Container::SystemGroups => self.system_groups.keys().cloned().collect(),
+            Container::MemberGroups => self.member_groups.keys().cloned().collect(),
+            Container::SystemFronters => self.system_fronters.keys().cloned().collect(),
+            Container::Message => self.messages.keys().cloned().collect(),
+        }
+    }
+
+    fn has_container(&self, container: Container, key: &str) -> bool {
+        match container {
+            Container::SystemMembers => self.system_members.contains_key(key),
+            Container::GroupMembers => self.group_members.contains_key(key),
+            Container::Group => self.groups.contains_key(key),
+            Container::SystemGroups => self.system_groups.contains_key(key),
+            Container::MemberGroups => self.member_groups.contains_key(key),
+            Container::SystemFronters => self.system_fronters.contains_key(key),
+            Container::Message => self.messages.contains_key(key),
+        }
+    }
+
+    // Called whenever a container is stored or gains embedded entities
+    pub(super) fn index(&mut self, container: Container, key: &str) {
+        for embedded in self.embedded_in(container, key) {
+            self.embeds.0.entry(embedded).or_default().insert((container, key.to_string()));
+        }
+    }
+
+    // Containers which embedded the entity when they were indexed
+    pub(super) fn containers(&self, entity: Entity, id: &str) -> impl Iterator<Item = (Container, &str)> {
+        self.embeds.0
+            .get(&(entity, id.to_string()))
+            .into_iter()
+            .flatten()
+            .map(|(container, key)| (*container, key.as_str()))
+    }
+
+    // Owned copy of `containers` for callers which update the containers
+    pub(super) fn containers_owned(&self, entity: Entity, id: &str) -> Vec<(Container, String)> {
+        self.containers(entity, id).map(|(container, key)| (container, key.to_string())).collect()
+    }
+
+    pub(super) fn rebuild_index(&mut self) {
+        self.embeds = EmbedIndex::default();
+
+        for container in CONTAINERS {
+            for key in self.container_keys(container) {
+                self.index(container, &key);
+            }
+        }
+    }
+
+    // Drops the containers which have been evicted or forgotten since they were indexed
+    pub(super) fn prune_index(&mut self) {
+        let mut embeds = std::mem::take(&mut self.embeds);
+
+        embeds.0.retain(|_, containers| {
+            containers.retain(|(container, key)| self.has_container(*container, key));
+            !containers.is_empty()
+        });
+
+        self.embeds = embeds;
+    }
+}
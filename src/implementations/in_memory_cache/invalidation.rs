@@ -0,0 +1,192 @@
+use crate::models::{Group, Member, MemberOrId};
+use crate::traits::notifier::Entity;
+use super::entry::{forget, forget_where, store, update_where};
+use super::switch_timeline::remove_switch_member;
+use super::{contains_group, contains_member, InMemoryCache};
+
+fn remove_member(members: &mut Vec<Member>, id: &str) -> bool {
+    let before = members.len();
+    members.retain(|member| member.id != id);
+
+    members.len() != before
+}
+
+fn remove_group_member(group: &mut Group, id: &str) -> bool {
+    group.members.as_mut().is_some_and(|members| remove_member(members, id))
+}
+
+fn remove_groups_member(groups: &mut [Group], id: &str) -> bool {
+    groups.iter_mut().fold(false, |changed, group| remove_group_member(group, id) | changed)
+}
+
+// Invalidations drop every copy of an entity across all partitions so the next request refetches it,
+// deletions instead remove it from the lists containing it, which stay valid
+impl InMemoryCache {
+    // Deleted entities are remembered as missing in every partition
+    fn mark_missing(&mut self, entity: Entity, id: &str) {
+        for partition in self.partitions.values_mut() {
+            store(&mut partition.not_found, &mut partition.usage, (entity, id.to_string()), ());
+        }
+    }
+
+    pub(super) fn invalidate_system(&mut self, id: &str) {
+        let key = (Entity::System, id.to_string());
+
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.systems, &mut partition.usage, id);
+            forget(&mut partition.system_settings, &mut partition.usage, id);
+            forget(&mut partition.system_autoproxy, &mut partition.usage, id);
+            forget(&mut partition.not_found, &mut partition.usage, &key);
+            forget_where(&mut partition.system_guild_settings, &mut partition.usage, |(system, _), _| system == id);
+        }
+    }
+
+    pub(super) fn invalidate_system_members(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.system_members, &mut partition.usage, id);
+        }
+    }
+
+    pub(super) fn invalidate_system_groups(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.system_groups, &mut partition.usage, id);
+        }
+    }
+
+    pub(super) fn invalidate_switches(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.system_switches, &mut partition.usage, id);
+            forget(&mut partition.system_fronters, &mut partition.usage, id);
+            forget_where(&mut partition.switches, &mut partition.usage, |(system, _), _| system == id);
+        }
+    }
+
+    // Lists embedding the member are dropped too so they don't serve an outdated copy
+    pub(super) fn invalidate_member(&mut self, id: &str) {
+        let key = (Entity::Member, id.to_string());
+
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.members, &mut partition.usage, id);
+            forget(&mut partition.member_groups, &mut partition.usage, id);
+            forget(&mut partition.not_found, &mut partition.usage, &key);
+            forget_where(&mut partition.member_guild_settings, &mut partition.usage, |(member, _), _| member == id);
+            forget_where(&mut partition.system_members, &mut partition.usage, |_, members| contains_member(members, id));
+            forget_where(&mut partition.group_members, &mut partition.usage, |_, members| contains_member(members, id));
+            forget_where(&mut partition.system_groups, &mut partition.usage, |_, groups| {
+                groups.iter().any(|group| group.members.as_ref().is_some_and(|members| contains_member(members, id)))
+            });
+            forget_where(&mut partition.system_fronters, &mut partition.usage, |_, switch| {
+                switch.members.iter().any(|member| matches!(member, MemberOrId::Member(member) if member.id == id))
+            });
+            forget_where(&mut partition.messages, &mut partition.usage, |_, message| message.member.as_ref().is_some_and(|member| member.id == id));
+        }
+    }
+
+    pub(super) fn invalidate_member_groups(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.member_groups, &mut partition.usage, id);
+        }
+    }
+
+    pub(super) fn invalidate_group(&mut self, id: &str) {
+        let key = (Entity::Group, id.to_string());
+
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.groups, &mut partition.usage, id);
+            forget(&mut partition.group_members, &mut partition.usage, id);
+            forget(&mut partition.not_found, &mut partition.usage, &key);
+            forget_where(&mut partition.system_groups, &mut partition.usage, |_, groups| contains_group(groups, id));
+            forget_where(&mut partition.member_groups, &mut partition.usage, |_, groups| contains_group(groups, id));
+        }
+    }
+
+    pub(super) fn invalidate_message(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.messages, &mut partition.usage, id);
+        }
+    }
+
+    // The system's members and groups are deleted along with it
+    pub(super) fn delete_system(&mut self, id: &str) {
+        let mut members = Vec::new();
+        let mut groups = Vec::new();
+
+        for partition in self.partitions.values() {
+            members.extend(partition.system_members.get(id).into_iter().flat_map(|entry| entry.peek()).map(|member| member.id.clone()));
+            groups.extend(partition.system_groups.get(id).into_iter().flat_map(|entry| entry.peek()).map(|group| group.id.clone()));
+        }
+
+        for member in members {
+            self.delete_member(&member);
+        }
+
+        for group in groups {
+            self.delete_group(&group);
+        }
+
+        self.invalidate_system(id);
+        self.invalidate_system_members(id);
+        self.invalidate_system_groups(id);
+        self.invalidate_switches(id);
+
+        for partition in self.partitions.values_mut() {
+            forget_where(&mut partition.messages, &mut partition.usage, |_, message| message.system.as_ref().is_some_and(|system| system.id == id));
+        }
+
+        self.mark_missing(Entity::System, id);
+    }
+
+    pub(super) fn delete_member(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.members, &mut partition.usage, id);
+            forget(&mut partition.member_groups, &mut partition.usage, id);
+            forget_where(&mut partition.member_guild_settings, &mut partition.usage, |(member, _), _| member == id);
+            update_where(&mut partition.system_members, &mut partition.usage, |_, members| remove_member(members, id));
+            update_where(&mut partition.group_members, &mut partition.usage, |_, members| remove_member(members, id));
+            update_where(&mut partition.groups, &mut partition.usage, |_, group| remove_group_member(group, id));
+            update_where(&mut partition.system_groups, &mut partition.usage, |_, groups| remove_groups_member(groups, id));
+            update_where(&mut partition.member_groups, &mut partition.usage, |_, groups| remove_groups_member(groups, id));
+            update_where(&mut partition.system_fronters, &mut partition.usage, |_, switch| remove_switch_member(switch, id));
+            update_where(&mut partition.switches, &mut partition.usage, |_, switch| remove_switch_member(switch, id));
+            update_where(&mut partition.system_switches, &mut partition.usage, |_, timeline| timeline.remove_member(id));
+            update_where(&mut partition.messages, &mut partition.usage, |_, message| {
+                message.member.take_if(|member| member.id == id).is_some()
+            });
+        }
+
+        self.mark_missing(Entity::Member, id);
+    }
+
+    pub(super) fn delete_group(&mut self, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.groups, &mut partition.usage, id);
+            forget(&mut partition.group_members, &mut partition.usage, id);
+            update_where(&mut partition.system_groups, &mut partition.usage, |_, groups| {
+                let before = groups.len();
+                groups.retain(|group| group.id != id);
+                groups.len() != before
+            });
+            update_where(&mut partition.member_groups, &mut partition.usage, |_, groups| {
+                let before = groups.len();
+                groups.retain(|group| group.id != id);
+                groups.len() != before
+            });
+        }
+
+        self.mark_missing(Entity::Group, id);
+    }
+
+    // The front is dropped rather than rolled back, the previous switch may not be cached
+    pub(super) fn delete_switch(&mut self, system: &str, id: &str) {
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.switches, &mut partition.usage, &(system.to_string(), id.to_string()));
+            forget_where(&mut partition.system_fronters, &mut partition.usage, |key, switch| key == system && switch.id == id);
+            update_where(&mut partition.system_switches, &mut partition.usage, |key, timeline| key == system && timeline.remove(id));
+        }
+    }
+
+    pub(super) fn delete_message(&mut self, id: &str) {
+        self.invalidate_message(id);
+        self.mark_missing(Entity::Message, id);
+    }
+}
@@ -0,0 +1,1198 @@
+mod aliases;
+mod entry;
+mod index;
+mod switch_timeline;
+mod derivation;
+mod invalidation;
+mod propagation;
+mod relations;
+mod snapshot;
+
+use std::collections::HashMap;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::implementations::hash_token;
+use crate::models::{AutoproxySettings, Group, Member, MemberGuildSettings, MemberOrId, Message, Switch, System, SystemGuildSettings, SystemSettings};
+use crate::privacy::PrivacyView;
+use crate::traits::provider::{Provider, ProviderResult};
+use crate::traits::notifier::{Entity, Notifier};
+use entry::{forget, store, stored_entries, CacheEntry, EntryMap, FreshMap, Usage};
+use index::{Container, EmbedIndex};
+use switch_timeline::{parse_timestamp, SwitchTimeline};
+
+pub(crate) use entry::EvictionPolicy;
+pub(crate) use switch_timeline::MAX_PAGE_SIZE;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CacheTtls {
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub systems: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub members: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub groups: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub switches: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub messages: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub settings: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub autoproxy: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub not_found: Duration,
+    // How long the latest switches of a system are trusted, new switches can happen at any time
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub front: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub aliases: Duration,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        Self {
+            systems: Duration::from_secs(5 * 60),
+            members: Duration::from_secs(5 * 60),
+            groups: Duration::from_secs(5 * 60),
+            // Switches and messages never change once created
+            switches: Duration::from_secs(24 * 60 * 60),
+            messages: Duration::from_secs(24 * 60 * 60),
+            settings: Duration::from_secs(5 * 60),
+            autoproxy: Duration::from_secs(10),
+            not_found: Duration::from_secs(30),
+            front: Duration::from_secs(30),
+            // IDs can be rerolled and Discord accounts unlinked, though rarely
+            aliases: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CacheLimits {
+    pub max_bytes: Option<usize>,
+    pub max_entries: Option<usize>,
+    #[serde(rename = "eviction")]
+    pub policy: EvictionPolicy,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(256 * 1024 * 1024),
+            max_entries: None,
+            policy: EvictionPolicy::Lru,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct CachePartition {
+    #[serde(with = "stored_entries")]
+    systems: HashMap<String, CacheEntry<System>>,
+    #[serde(with = "stored_entries")]
+    system_settings: HashMap<String, CacheEntry<SystemSettings>>,
+    #[serde(with = "stored_entries")]
+    system_guild_settings: HashMap<(String, String), CacheEntry<SystemGuildSettings>>,
+    #[serde(with = "stored_entries")]
+    system_autoproxy: HashMap<String, CacheEntry<AutoproxySettings>>,
+    #[serde(with = "stored_entries")]
+    system_members: HashMap<String, CacheEntry<Vec<Member>>>,
+    #[serde(with = "stored_entries")]
+    members: HashMap<String, CacheEntry<Member>>,
+    #[serde(with = "stored_entries")]
+    member_groups: HashMap<String, CacheEntry<Vec<Group>>>,
+    #[serde(with = "stored_entries")]
+    member_guild_settings: HashMap<(String, String), CacheEntry<MemberGuildSettings>>,
+    #[serde(with = "stored_entries")]
+    system_groups: HashMap<String, CacheEntry<Vec<Group>>>,
+    #[serde(with = "stored_entries")]
+    groups: HashMap<String, CacheEntry<Group>>,
+    #[serde(with = "stored_entries")]
+    group_members: HashMap<String, CacheEntry<Vec<Member>>>,
+    #[serde(with = "stored_entries")]
+    system_switches: HashMap<String, CacheEntry<SwitchTimeline>>,
+    #[serde(with = "stored_entries")]
+    system_fronters: HashMap<String, CacheEntry<Switch>>,
+    #[serde(with = "stored_entries")]
+    switches: HashMap<(String, String), CacheEntry<Switch>>,
+    #[serde(with = "stored_entries")]
+    messages: HashMap<String, CacheEntry<Message>>,
+    #[serde(with = "stored_entries")]
+    not_found: HashMap<(Entity, String), CacheEntry<()>>,
+    #[serde(with = "stored_entries")]
+    aliases: HashMap<(Entity, String), CacheEntry<String>>,
+    #[serde(skip)]
+    usage: Usage,
+    #[serde(skip)]
+    embeds: EmbedIndex,
+}
+
+impl CachePartition {
+    fn maps_mut<'a>(&'a mut self, ttls: &CacheTtls) -> [(&'a mut dyn EntryMap, Duration); 17] {
+        [
+            (&mut self.systems, ttls.systems),
+            (&mut self.system_settings, ttls.settings),
+            (&mut self.system_guild_settings, ttls.settings),
+            (&mut self.system_autoproxy, ttls.autoproxy),
+            (&mut self.system_members, ttls.members),
+            (&mut self.members, ttls.members),
+            (&mut self.member_groups, ttls.groups),
+            (&mut self.member_guild_settings, ttls.settings),
+            (&mut self.system_groups, ttls.groups),
+            (&mut self.groups, ttls.groups),
+            (&mut self.group_members, ttls.members),
+            (&mut self.system_switches, ttls.switches),
+            (&mut self.system_fronters, ttls.front),
+            (&mut self.switches, ttls.switches),
+            (&mut self.messages, ttls.messages),
+            (&mut self.not_found, ttls.not_found),
+            (&mut self.aliases, ttls.aliases),
+        ]
+    }
+
+    // Moves the cached front forward to a newer switch, `latest` allows creating it when none is cached yet
+    fn advance_front(&mut self, ttls: &CacheTtls, system: &str, switch: &Switch, latest: bool) {
+        let Some(timestamp) = parse_timestamp(&switch.timestamp) else {
+            return;
+        };
+
+        let newer = match self.system_fronters.get(system) {
+            Some(current) => parse_timestamp(&current.peek().timestamp).is_none_or(|current| current < timestamp),
+            None => latest,
+        };
+
+        if !newer {
+            return;
+        }
+
+        // Switch lists only carry member IDs while the fronters endpoint embeds the members themselves
+        let members: Option<Vec<MemberOrId>> = switch.members
+            .iter()
+            .map(|member| match member {
+                MemberOrId::Member(member) => Some(MemberOrId::Member(member.clone())),
+                MemberOrId::Id(id) => self.members.fresh(id.as_str(), ttls.members).map(|member| MemberOrId::Member(member.clone())),
+            })
+            .collect();
+
+        match members {
+            Some(members) => {
+                let front = Switch {
+                    id: switch.id.clone(),
+                    timestamp: switch.timestamp.clone(),
+                    members,
+                };
+
+                store(&mut self.system_fronters, &mut self.usage, system.to_string(), front);
+                self.index(Container::SystemFronters, system);
+            }
+            None => {
+                forget(&mut self.system_fronters, &mut self.usage, system);
+            }
+        }
+    }
+}
+
+pub(crate) struct InMemoryCache {
+    // Keyed by the hash of the token the data was fetched with, anonymous data lives under None
+    partitions: HashMap<Option<String>, CachePartition>,
+    ttls: CacheTtls,
+    limits: CacheLimits,
+}
+
+fn public_result<T: PrivacyView>(value: &T) -> ProviderResult<T> {
+    match value.public_view() {
+        Some(value) => ProviderResult::Ok(value),
+        None => ProviderResult::NotFound,
+    }
+}
+
+fn stale_or_miss<T: Clone>(stale: Option<(&T, Duration)>) -> ProviderResult<T> {
+    match stale {
+        Some((value, expired_for)) => ProviderResult::Stale(value.clone(), expired_for),
+        None => ProviderResult::Miss,
+    }
+}
+
+fn contains_member(members: &[Member], id: &str) -> bool {
+    members.iter().any(|member| member.id == id)
+}
+
+fn contains_group(groups: &[Group], id: &str) -> bool {
+    groups.iter().any(|group| group.id == id)
+}
+
+fn embedded_members(groups: &[Group]) -> Vec<Member> {
+    groups.iter().flat_map(|group| group.members.iter().flatten().cloned()).collect()
+}
+
+fn switch_members(switch: &Switch) -> Vec<Member> {
+    switch.members
+        .iter()
+        .filter_map(|member| match member {
+            MemberOrId::Member(member) => Some(member.clone()),
+            MemberOrId::Id(_) => None,
+        })
+        .collect()
+}
+
+impl InMemoryCache {
+    pub fn new(ttls: CacheTtls, limits: CacheLimits) -> Self {
+        Self {
+            partitions: HashMap::new(),
+            ttls,
+            limits,
+        }
+    }
+
+    fn partition(&self, token: Option<&str>) -> Option<&CachePartition> {
+        self.partitions.get(&token.map(hash_token))
+    }
+
+    // Anonymous requests can be answered from an owner's view of the data fetched by another token
+    fn owner_partitions(&self, token: Option<&str>) -> impl Iterator<Item = &CachePartition> {
+        let anonymous = token.is_none();
+
+        self.partitions
+            .iter()
+            .filter(move |(key, _)| anonymous && key.is_some())
+            .map(|(_, partition)| partition)
+    }
+
+    fn partition_mut(&mut self, token: Option<&str>) -> &mut CachePartition {
+        self.partitions.entry(token.map(hash_token)).or_default()
+    }
+
+    fn known_missing(&self, token: Option<&str>, entity: Entity, id: &str) -> bool {
+        self.partition(token).is_some_and(|partition| partition.not_found.fresh(&(entity, id.to_string()), self.ttls.not_found).is_some())
+    }
+
+    // Any data received for an entity proves it exists, whichever token it was fetched with
+    fn clear_not_found(&mut self, entity: Entity, id: &str) {
+        let key = (entity, id.to_string());
+
+        for partition in self.partitions.values_mut() {
+            forget(&mut partition.not_found, &mut partition.usage, &key);
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        let mut usage = Usage::default();
+
+        for partition in self.partitions.values() {
+            usage.add(partition.usage);
+        }
+
+        usage
+    }
+
+    fn over_budget(&self, usage: Usage, ratio: f64) -> bool {
+        let over_bytes = self.limits.max_bytes.is_some_and(|max| usage.bytes as f64 > max as f64 * ratio);
+        let over_entries = self.limits.max_entries.is_some_and(|max| usage.entries as f64 > max as f64 * ratio);
+
+        over_bytes || over_entries
+    }
+
+    // Evicts the least valuable entries across every partition until usage is back to 90% of the budget
+    fn enforce_limits(&mut self) {
+        let mut usage = self.usage();
+
+        if !self.over_budget(usage, 1.0) {
+            return;
+        }
+
+        let policy = self.limits.policy;
+        let ttls = self.ttls.clone();
+        let mut ranks = Vec::with_capacity(usage.entries);
+
+        for partition in self.partitions.values_mut() {
+            for (map, ttl) in partition.maps_mut(&ttls) {
+                map.ranks(policy, ttl, &mut ranks);
+            }
+        }
+
+        ranks.sort_unstable_by_key(|(rank, _)| *rank);
+
+        let mut threshold = 0;
+        for (rank, size) in ranks {
+            if !self.over_budget(usage, 0.9) {
+                break;
+            }
+
+            usage.sub(Usage { bytes: size, entries: 1 });
+            threshold = rank + 1;
+        }
+
+        for partition in self.partitions.values_mut() {
+            let mut evicted = Usage::default();
+
+            for (map, ttl) in partition.maps_mut(&ttls) {
+                evicted.add(map.evict_below(policy, ttl, threshold));
+            }
+
+            partition.usage.sub(evicted);
+            partition.prune_index();
+        }
+
+        self.partitions.retain(|_, partition| partition.usage.entries > 0);
+    }
+}
+
+#[async_trait]
+impl Notifier for InMemoryCache {
+    async fn notify_system(&mut self, token: Option<&str>, system: &System) {
+        self.clear_not_found(Entity::System, &system.id);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_system(system);
+        store(&mut partition.systems, &mut partition.usage, system.id.clone(), system.clone());
+        partition.propagate_system(system);
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_settings(&mut self, token: Option<&str>, system: &str, settings: &SystemSettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        store(&mut partition.system_settings, &mut partition.usage, system.to_string(), settings.clone());
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_guild_settings(&mut self, token: Option<&str>, system: &str, guild: &str, settings: &SystemGuildSettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        store(&mut partition.system_guild_settings, &mut partition.usage, (system.to_string(), guild.to_string()), settings.clone());
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_autoproxy(&mut self, token: Option<&str>, system: &str, settings: &AutoproxySettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        store(&mut partition.system_autoproxy, &mut partition.usage, system.to_string(), settings.clone());
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_members(&mut self, token: Option<&str>, system: &str, members: &[Member]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_members(members);
+        store(&mut partition.system_members, &mut partition.usage, system.to_string(), members.to_vec());
+        partition.index(Container::SystemMembers, system);
+
+        for member in members {
+            store(&mut partition.members, &mut partition.usage, member.id.clone(), member.clone());
+        }
+        partition.propagate_members(members);
+
+        for member in members {
+            self.clear_not_found(Entity::Member, &member.id);
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_member(&mut self, token: Option<&str>, member: &Member) {
+        self.clear_not_found(Entity::Member, &member.id);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_members(std::slice::from_ref(member));
+        store(&mut partition.members, &mut partition.usage, member.id.clone(), member.clone());
+
+        partition.propagate_members(std::slice::from_ref(member));
+
+        self.enforce_limits();
+    }
+
+    async fn notify_member_groups(&mut self, token: Option<&str>, member: &str, groups: &[Group]) {
+        let member = &self.canonical(token, Entity::Member, member);
+
+        self.clear_not_found(Entity::Member, member);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_groups(groups);
+        store(&mut partition.member_groups, &mut partition.usage, member.to_string(), groups.to_vec());
+        partition.index(Container::MemberGroups, member);
+
+        for group in groups {
+            store(&mut partition.groups, &mut partition.usage, group.id.clone(), group.clone());
+            partition.index(Container::Group, &group.id);
+        }
+        partition.propagate_groups(groups);
+        partition.propagate_members(&embedded_members(groups));
+        partition.reconcile_member_groups(member, groups);
+
+        for group in groups {
+            self.clear_not_found(Entity::Group, &group.id);
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_member_guild_settings(&mut self, token: Option<&str>, member: &str, guild: &str, settings: &MemberGuildSettings) {
+        let member = &self.canonical(token, Entity::Member, member);
+
+        self.clear_not_found(Entity::Member, member);
+
+        let partition = self.partition_mut(token);
+
+        store(&mut partition.member_guild_settings, &mut partition.usage, (member.to_string(), guild.to_string()), settings.clone());
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_groups(&mut self, token: Option<&str>, system: &str, groups: &[Group]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_groups(groups);
+        store(&mut partition.system_groups, &mut partition.usage, system.to_string(), groups.to_vec());
+        partition.index(Container::SystemGroups, system);
+
+        for group in groups {
+            store(&mut partition.groups, &mut partition.usage, group.id.clone(), group.clone());
+            partition.index(Container::Group, &group.id);
+        }
+        partition.propagate_groups(groups);
+        partition.propagate_members(&embedded_members(groups));
+
+        for group in groups {
+            if let Some(members) = &group.members {
+                partition.reconcile_group_members(&group.id, members);
+            }
+        }
+
+        for group in groups {
+            self.clear_not_found(Entity::Group, &group.id);
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_group(&mut self, token: Option<&str>, group: &Group) {
+        self.clear_not_found(Entity::Group, &group.id);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_groups(std::slice::from_ref(group));
+        store(&mut partition.groups, &mut partition.usage, group.id.clone(), group.clone());
+        partition.index(Container::Group, &group.id);
+
+        partition.propagate_groups(std::slice::from_ref(group));
+        partition.propagate_members(&embedded_members(std::slice::from_ref(group)));
+
+        if let Some(members) = &group.members {
+            partition.reconcile_group_members(&group.id, members);
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_group_members(&mut self, token: Option<&str>, group: &str, members: &[Member]) {
+        let group = &self.canonical(token, Entity::Group, group);
+
+        self.clear_not_found(Entity::Group, group);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_members(members);
+        store(&mut partition.group_members, &mut partition.usage, group.to_string(), members.to_vec());
+        partition.index(Container::GroupMembers, group);
+
+        for member in members {
+            store(&mut partition.members, &mut partition.usage, member.id.clone(), member.clone());
+        }
+        partition.propagate_members(members);
+        partition.reconcile_group_members(group, members);
+
+        for member in members {
+            self.clear_not_found(Entity::Member, &member.id);
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_switches(&mut self, token: Option<&str>, system: &str, before: &str, limit: u64, switches: &[Switch]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        let mut timeline = forget(&mut partition.system_switches, &mut partition.usage, system).unwrap_or_default();
+        timeline.merge_page(before, limit, switches);
+        store(&mut partition.system_switches, &mut partition.usage, system.to_string(), timeline);
+
+        for switch in switches {
+            store(&mut partition.switches, &mut partition.usage, (system.to_string(), switch.id.clone()), switch.clone());
+        }
+
+        // The first switch of the head page is the current front
+        if let Some(newest) = switches.first() {
+            let ttls = self.ttls.clone();
+            self.partition_mut(token).advance_front(&ttls, system, newest, before.is_empty());
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_system_fronters(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_members(&switch_members(switch));
+        store(&mut partition.system_fronters, &mut partition.usage, system.to_string(), switch.clone());
+        partition.index(Container::SystemFronters, system);
+        partition.propagate_members(&switch_members(switch));
+
+        self.enforce_limits();
+    }
+
+    async fn notify_switch(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+
+        let ttls = self.ttls.clone();
+        let partition = self.partition_mut(token);
+
+        partition.learn_members(&switch_members(switch));
+        store(&mut partition.switches, &mut partition.usage, (system.to_string(), switch.id.clone()), switch.clone());
+        partition.advance_front(&ttls, system, switch, false);
+
+        self.enforce_limits();
+    }
+
+    async fn notify_message(&mut self, token: Option<&str>, message: &Message) {
+        self.clear_not_found(Entity::Message, &message.id);
+
+        let partition = self.partition_mut(token);
+
+        partition.learn_message(message);
+        store(&mut partition.messages, &mut partition.usage, message.id.clone(), message.clone());
+        partition.index(Container::Message, &message.id);
+        partition.propagate_members(message.member.as_slice());
+        if let Some(system) = &message.system {
+            partition.propagate_system(system);
+        }
+
+        self.enforce_limits();
+    }
+
+    // Remembered under the ID as requested rather than the entity an alias pointed to, as a 404 for an alias
+    // may only mean the alias is gone, such as an unlinked Discord account. The alias is dropped along with it
+    async fn notify_not_found(&mut self, token: Option<&str>, entity: Entity, id: &str) {
+        let id = id.to_lowercase();
+
+        self.forget_alias(token, entity, &id);
+
+        let partition = self.partition_mut(token);
+
+        store(&mut partition.not_found, &mut partition.usage, (entity, id), ());
+
+        self.enforce_limits();
+    }
+
+    async fn notify_alias(&mut self, token: Option<&str>, entity: Entity, alias: &str, id: &str) {
+        self.partition_mut(token).learn_alias(entity, alias, id);
+
+        self.enforce_limits();
+    }
+
+    async fn notify_deleted(&mut self, entity: Entity, id: &str) {
+        let id = &self.canonical(None, entity, id);
+
+        match entity {
+            Entity::System => self.delete_system(id),
+            Entity::Member => self.delete_member(id),
+            Entity::Group => self.delete_group(id),
+            Entity::Message => self.delete_message(id),
+        }
+
+        self.enforce_limits();
+    }
+
+    async fn notify_switch_deleted(&mut self, system: &str, switch: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.delete_switch(system, switch);
+    }
+
+    async fn notify_invalidated(&mut self, entity: Entity, id: &str) {
+        let id = &self.canonical(None, entity, id);
+
+        match entity {
+            Entity::System => self.invalidate_system(id),
+            Entity::Member => self.invalidate_member(id),
+            Entity::Group => self.invalidate_group(id),
+            Entity::Message => self.invalidate_message(id),
+        }
+    }
+
+    async fn notify_alias_invalidated(&mut self, entity: Entity, alias: &str) {
+        self.forget_alias(None, entity, alias);
+    }
+
+    async fn notify_system_members_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.invalidate_system_members(system);
+    }
+
+    async fn notify_system_groups_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.invalidate_system_groups(system);
+    }
+
+    async fn notify_system_switches_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.invalidate_switches(system);
+    }
+
+    async fn notify_member_groups_invalidated(&mut self, member: &str) {
+        let member = &self.canonical(None, Entity::Member, member);
+
+        self.invalidate_member_groups(member);
+    }
+
+    async fn resolve_uuid(&mut self, entity: Entity, uuid: &str) -> Option<String> {
+        self.find_uuid(entity, uuid)
+    }
+}
+
+#[async_trait]
+impl Provider for InMemoryCache {
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(system) = self.partition(token).and_then(|partition| partition.systems.fresh(id, self.ttls.systems)) {
+            ProviderResult::Ok(system.clone())
+        } else if let Some(system) = self.owner_partitions(token).find_map(|partition| partition.systems.fresh(id, self.ttls.systems).filter(|system| system.is_owner_view())) {
+            public_result(system)
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.systems.stale(id, self.ttls.systems)))
+        }
+    }
+
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(settings) = self.partition(token).and_then(|partition| partition.system_settings.fresh(id, self.ttls.settings)) {
+            ProviderResult::Ok(settings.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_settings.stale(id, self.ttls.settings)))
+        }
+    }
+
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if let Some(settings) = self.partition(token).and_then(|partition| partition.system_guild_settings.fresh(&(id.to_string(), guild.to_string()), self.ttls.settings)) {
+            ProviderResult::Ok(settings.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_guild_settings.stale(&(id.to_string(), guild.to_string()), self.ttls.settings)))
+        }
+    }
+
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(settings) = self.partition(token).and_then(|partition| partition.system_autoproxy.fresh(id, self.ttls.autoproxy)) {
+            ProviderResult::Ok(settings.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_autoproxy.stale(id, self.ttls.autoproxy)))
+        }
+    }
+
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(members) = self.partition(token).and_then(|partition| partition.system_members.fresh(id, self.ttls.members)) {
+            ProviderResult::Ok(members.clone())
+        } else if let Some((system, members)) = self.owner_partitions(token).find_map(|partition| {
+            Some((partition.systems.fresh(id, self.ttls.systems).filter(|system| system.is_owner_view())?, partition.system_members.fresh(id, self.ttls.members)?))
+        }) {
+            if system.member_list_private() {
+                ProviderResult::Unauthorized
+            } else {
+                ProviderResult::Ok(members.iter().filter_map(Member::public_view).collect())
+            }
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_members.stale(id, self.ttls.members)))
+        }
+    }
+
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        if self.known_missing(token, Entity::Member, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(member) = self.partition(token).and_then(|partition| partition.members.fresh(id, self.ttls.members)) {
+            ProviderResult::Ok(member.clone())
+        } else if let Some(member) = self.partition(token).and_then(|partition| partition.derive_member(id, &self.ttls)) {
+            ProviderResult::Ok(member)
+        } else if let Some(member) = self.owner_partitions(token).find_map(|partition| partition.members.fresh(id, self.ttls.members).filter(|member| member.is_owner_view())) {
+            public_result(member)
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.members.stale(id, self.ttls.members)))
+        }
+    }
+
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        if self.known_missing(token, Entity::Member, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(groups) = self.partition(token).and_then(|partition| partition.member_groups.fresh(id, self.ttls.groups)) {
+            ProviderResult::Ok(groups.clone())
+        } else if let Some(groups) = self.partition(token).and_then(|partition| partition.derive_member_groups(id, &self.ttls)) {
+            ProviderResult::Ok(groups)
+        } else if let Some((member, groups)) = self.owner_partitions(token).find_map(|partition| {
+            Some((partition.members.fresh(id, self.ttls.members).filter(|member| member.is_owner_view())?, partition.member_groups.fresh(id, self.ttls.groups)?))
+        }) {
+            if member.is_hidden() {
+                ProviderResult::NotFound
+            } else {
+                ProviderResult::Ok(groups.iter().filter_map(Group::public_view).collect())
+            }
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.member_groups.stale(id, self.ttls.groups)))
+        }
+    }
+
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        if let Some(settings) = self.partition(token).and_then(|partition| partition.member_guild_settings.fresh(&(id.to_string(), guild.to_string()), self.ttls.settings)) {
+            ProviderResult::Ok(settings.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.member_guild_settings.stale(&(id.to_string(), guild.to_string()), self.ttls.settings)))
+        }
+    }
+
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        let result = if let Some(groups) = self.partition(token).and_then(|partition| partition.system_groups.fresh(id, self.ttls.groups)) {
+            ProviderResult::Ok(groups.clone())
+        } else if let Some((system, groups)) = self.owner_partitions(token).find_map(|partition| {
+            Some((partition.systems.fresh(id, self.ttls.systems).filter(|system| system.is_owner_view())?, partition.system_groups.fresh(id, self.ttls.groups)?))
+        }) {
+            if system.group_list_private() {
+                return ProviderResult::Unauthorized;
+            }
+
+            ProviderResult::Ok(groups.iter().filter_map(Group::public_view).collect())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_groups.stale(id, self.ttls.groups)))
+        };
+
+        let (mut groups, expired_for) = match result {
+            ProviderResult::Ok(groups) => (groups, None),
+            ProviderResult::Stale(groups, expired_for) => (groups, Some(expired_for)),
+            result => return result,
+        };
+
+        if with_member {
+            // Check that we have member information for each group
+            if !groups.iter().all(|g| g.members.is_some()) {
+                return ProviderResult::Miss;
+            }
+        } else {
+            for group in groups.iter_mut() {
+                group.members = None;
+            }
+        }
+
+        match expired_for {
+            Some(expired_for) => ProviderResult::Stale(groups, expired_for),
+            None => ProviderResult::Ok(groups),
+        }
+    }
+
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        let id = &self.canonical(token, Entity::Group, id);
+
+        if self.known_missing(token, Entity::Group, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(group) = self.partition(token).and_then(|partition| partition.groups.fresh(id, self.ttls.groups)) {
+            ProviderResult::Ok(Group { members: None, ..group.clone() })
+        } else if let Some(group) = self.partition(token).and_then(|partition| partition.derive_group(id, &self.ttls)) {
+            ProviderResult::Ok(group)
+        } else if let Some(group) = self.owner_partitions(token).find_map(|partition| partition.groups.fresh(id, self.ttls.groups).filter(|group| group.is_owner_view())) {
+            public_result(&Group { members: None, ..group.clone() })
+        } else {
+            match stale_or_miss(self.partition(token).and_then(|partition| partition.groups.stale(id, self.ttls.groups))) {
+                ProviderResult::Stale(group, expired_for) => ProviderResult::Stale(Group { members: None, ..group }, expired_for),
+                result => result,
+            }
+        }
+    }
+
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        let id = &self.canonical(token, Entity::Group, id);
+
+        if self.known_missing(token, Entity::Group, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(members) = self.partition(token).and_then(|partition| partition.group_members.fresh(id, self.ttls.members)) {
+            ProviderResult::Ok(members.clone())
+        } else if let Some(members) = self.partition(token).and_then(|partition| partition.derive_group_members(id, &self.ttls)) {
+            ProviderResult::Ok(members)
+        } else if let Some((group, members)) = self.owner_partitions(token).find_map(|partition| {
+            Some((partition.groups.fresh(id, self.ttls.groups).filter(|group| group.is_owner_view())?, partition.group_members.fresh(id, self.ttls.members)?))
+        }) {
+            if group.is_hidden() {
+                ProviderResult::NotFound
+            } else if group.member_list_private() {
+                ProviderResult::Unauthorized
+            } else {
+                ProviderResult::Ok(members.iter().filter_map(Member::public_view).collect())
+            }
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.group_members.stale(id, self.ttls.members)))
+        }
+    }
+
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        let page = self.partition(token)
+            .and_then(|partition| partition.system_switches.fresh(id, self.ttls.switches))
+            .and_then(|timeline| timeline.page(before, limit, self.ttls.front));
+
+        match page {
+            Some(switches) => ProviderResult::Ok(switches),
+            None => ProviderResult::Miss,
+        }
+    }
+
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(switch) = self.partition(token).and_then(|partition| partition.system_fronters.fresh(id, self.ttls.front)) {
+            ProviderResult::Ok(switch.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.system_fronters.stale(id, self.ttls.front)))
+        }
+    }
+
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if let Some(switch) = self.partition(token).and_then(|partition| partition.switches.fresh(&(id.to_string(), switch_id.to_string()), self.ttls.switches)) {
+            ProviderResult::Ok(switch.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.switches.stale(&(id.to_string(), switch_id.to_string()), self.ttls.switches)))
+        }
+    }
+
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        let id = &self.canonical(token, Entity::Message, id);
+
+        if self.known_missing(token, Entity::Message, id) {
+            return ProviderResult::NotFound;
+        }
+
+        if let Some(message) = self.partition(token).and_then(|partition| partition.messages.fresh(id, self.ttls.messages)) {
+            ProviderResult::Ok(message.clone())
+        } else {
+            stale_or_miss(self.partition(token).and_then(|partition| partition.messages.stale(id, self.ttls.messages)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::*;
+
+    const TOKEN: Option<&str> = Some("owner token");
+
+    fn cache() -> InMemoryCache {
+        InMemoryCache::new(CacheTtls::default(), CacheLimits::default())
+    }
+
+    fn system(privacy: Value) -> System {
+        serde_json::from_value(json!({ "id": "sysid", "uuid": "00000000-0000-0000-0000-000000000001", "privacy": privacy })).unwrap()
+    }
+
+    fn member(id: &str, privacy: Value) -> Member {
+        serde_json::from_value(json!({
+            "id": id,
+            "uuid": format!("00000000-0000-0000-0000-0000000{id}"),
+            "name": "Private name",
+            "display_name": "Shown name",
+            "proxy_tags": [],
+            "keep_proxy": false,
+            "privacy": privacy,
+        })).unwrap()
+    }
+
+    fn group(privacy: Value) -> Group {
+        serde_json::from_value(json!({ "id": "grpid", "uuid": "00000000-0000-0000-0000-000000000002", "name": "Group", "privacy": privacy })).unwrap()
+    }
+
+    fn settings() -> SystemSettings {
+        serde_json::from_value(json!({
+            "timezone": "UTC",
+            "pings_enabled": true,
+            "latch_timeout": null,
+            "member_default_privacy": false,
+            "group_default_privacy": false,
+            "show_private_info": true,
+            "member_limit": 1000,
+            "group_limit": 250,
+        })).unwrap()
+    }
+
+    fn limited(policy: EvictionPolicy) -> InMemoryCache {
+        InMemoryCache::new(CacheTtls::default(), CacheLimits { max_bytes: None, max_entries: Some(3), policy })
+    }
+
+    async fn has_settings(cache: &InMemoryCache, token: Option<&str>, system: &str) -> bool {
+        matches!(cache.get_system_settings(token, system).await, ProviderResult::Ok(_))
+    }
+
+    fn members() -> Vec<Member> {
+        vec![
+            member("shown", json!({ "visibility": "public", "name_privacy": "private" })),
+            member("hides", json!({ "visibility": "private" })),
+        ]
+    }
+
+    #[tokio::test]
+    async fn alias_not_found_leaves_the_entity_alone() {
+        let mut cache = cache();
+        let system = system(Value::Null);
+        cache.notify_system(TOKEN, &system).await;
+        cache.notify_alias(TOKEN, Entity::System, "123456789", "sysid").await;
+        assert_eq!(cache.get_system(TOKEN, "123456789").await, ProviderResult::Ok(system.clone()));
+
+        // The Discord account was unlinked, PluralKit no longer finds a system for it
+        cache.notify_not_found(TOKEN, Entity::System, "123456789").await;
+
+        assert_eq!(cache.get_system(TOKEN, "123456789").await, ProviderResult::NotFound);
+        assert_eq!(cache.get_system(TOKEN, "sysid").await, ProviderResult::Ok(system));
+        assert_eq!(cache.canonical(None, Entity::System, "123456789"), "123456789");
+    }
+
+    #[tokio::test]
+    async fn member_is_answered_from_the_owner_view() {
+        let mut cache = cache();
+        let owned = member("shown", json!({ "visibility": "public", "name_privacy": "private" }));
+        cache.notify_member(TOKEN, &owned).await;
+
+        assert_eq!(cache.get_member(TOKEN, "shown").await, ProviderResult::Ok(owned.clone()));
+        assert_eq!(cache.get_member(None, "shown").await, ProviderResult::Ok(owned.public_view().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn hidden_member_is_not_found_anonymously() {
+        let mut cache = cache();
+        cache.notify_member(TOKEN, &member("hides", json!({ "visibility": "private" }))).await;
+
+        assert_eq!(cache.get_member(None, "hides").await, ProviderResult::NotFound);
+    }
+
+    #[tokio::test]
+    async fn public_member_is_not_an_owner_view() {
+        let mut cache = cache();
+        cache.notify_member(Some("other token"), &member("shown", Value::Null)).await;
+
+        assert_eq!(cache.get_member(None, "shown").await, ProviderResult::Miss);
+    }
+
+    #[tokio::test]
+    async fn group_is_answered_from_the_owner_view_without_its_members() {
+        let mut cache = cache();
+        let owned = Group { members: Some(members()), ..group(json!({ "visibility": "public", "list_privacy": "public" })) };
+        cache.notify_group(TOKEN, &owned).await;
+
+        let public = Group { members: None, ..owned.public_view().unwrap() };
+
+        assert_eq!(cache.get_group(TOKEN, "grpid").await, ProviderResult::Ok(Group { members: None, ..owned }));
+        assert_eq!(cache.get_group(None, "grpid").await, ProviderResult::Ok(public));
+    }
+
+    #[tokio::test]
+    async fn system_members_are_filtered_from_the_owner_view() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system(json!({ "member_list_privacy": "public" }))).await;
+        cache.notify_system_members(TOKEN, "sysid", &members()).await;
+
+        assert_eq!(cache.get_system_members(TOKEN, "sysid").await, ProviderResult::Ok(members()));
+        assert_eq!(cache.get_system_members(None, "sysid").await, ProviderResult::Ok(vec![members()[0].public_view().unwrap()]));
+    }
+
+    #[tokio::test]
+    async fn private_system_member_list_is_unauthorized() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system(json!({ "member_list_privacy": "private" }))).await;
+        cache.notify_system_members(TOKEN, "sysid", &members()).await;
+
+        assert_eq!(cache.get_system_members(None, "sysid").await, ProviderResult::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn system_members_need_the_owner_view_of_the_system() {
+        let mut cache = cache();
+        cache.notify_system_members(TOKEN, "sysid", &members()).await;
+
+        assert_eq!(cache.get_system_members(None, "sysid").await, ProviderResult::Miss);
+    }
+
+    #[tokio::test]
+    async fn group_members_are_filtered_from_the_owner_view() {
+        let mut cache = cache();
+        cache.notify_group(TOKEN, &group(json!({ "visibility": "public", "list_privacy": "public" }))).await;
+        cache.notify_group_members(TOKEN, "grpid", &members()).await;
+
+        assert_eq!(cache.get_group_members(TOKEN, "grpid").await, ProviderResult::Ok(members()));
+        assert_eq!(cache.get_group_members(None, "grpid").await, ProviderResult::Ok(vec![members()[0].public_view().unwrap()]));
+    }
+
+    #[tokio::test]
+    async fn private_group_member_list_is_unauthorized() {
+        let mut cache = cache();
+        cache.notify_group(TOKEN, &group(json!({ "list_privacy": "private" }))).await;
+        cache.notify_group_members(TOKEN, "grpid", &members()).await;
+
+        assert_eq!(cache.get_group_members(None, "grpid").await, ProviderResult::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn hidden_group_members_are_not_found() {
+        let mut cache = cache();
+        cache.notify_group(TOKEN, &group(json!({ "visibility": "private", "list_privacy": "public" }))).await;
+        cache.notify_group_members(TOKEN, "grpid", &members()).await;
+
+        assert_eq!(cache.get_group_members(None, "grpid").await, ProviderResult::NotFound);
+    }
+
+    #[tokio::test]
+    async fn uuids_of_embedded_members_are_resolved() {
+        let mut cache = cache();
+        let switch: Switch = serde_json::from_value(json!({ "id": "switch", "timestamp": "2024-01-01T00:00:00Z", "members": [members()[0]] })).unwrap();
+        cache.notify_system_fronters(TOKEN, "sysid", &switch).await;
+
+        assert_eq!(cache.resolve_uuid(Entity::Member, "00000000-0000-0000-0000-0000000SHOWN").await, Some("shown".to_string()));
+        assert_eq!(cache.resolve_uuid(Entity::Member, "00000000-0000-0000-0000-0000000hides").await, None);
+    }
+
+    // Going over the limit evicts down to 90% of it, two of the four entries here
+    #[tokio::test]
+    async fn lru_evicts_the_least_recently_used_entries() {
+        let mut cache = limited(EvictionPolicy::Lru);
+        for system in ["aaaaa", "bbbbb", "ccccc"] {
+            cache.notify_system_settings(TOKEN, system, &settings()).await;
+        }
+        assert!(has_settings(&cache, TOKEN, "aaaaa").await);
+
+        cache.notify_system_settings(TOKEN, "ddddd", &settings()).await;
+
+        assert!(!has_settings(&cache, TOKEN, "bbbbb").await);
+        assert!(!has_settings(&cache, TOKEN, "ccccc").await);
+        assert!(has_settings(&cache, TOKEN, "aaaaa").await);
+        assert!(has_settings(&cache, TOKEN, "ddddd").await);
+    }
+
+    #[tokio::test]
+    async fn lfu_evicts_the_least_frequently_used_entries() {
+        let mut cache = limited(EvictionPolicy::Lfu);
+        for system in ["aaaaa", "bbbbb", "ccccc"] {
+            cache.notify_system_settings(TOKEN, system, &settings()).await;
+        }
+        for system in ["bbbbb", "bbbbb", "ccccc", "aaaaa"] {
+            assert!(has_settings(&cache, TOKEN, system).await);
+        }
+
+        cache.notify_system_settings(TOKEN, "ddddd", &settings()).await;
+
+        assert!(!has_settings(&cache, TOKEN, "ddddd").await);
+        assert!(!has_settings(&cache, TOKEN, "ccccc").await);
+        assert!(has_settings(&cache, TOKEN, "aaaaa").await);
+        assert!(has_settings(&cache, TOKEN, "bbbbb").await);
+    }
+
+    #[tokio::test]
+    async fn limits_are_shared_by_every_partition() {
+        let mut cache = limited(EvictionPolicy::Lru);
+        cache.notify_system_settings(TOKEN, "aaaaa", &settings()).await;
+        cache.notify_system_settings(None, "bbbbb", &settings()).await;
+        cache.notify_system_settings(Some("other token"), "ccccc", &settings()).await;
+
+        cache.notify_system_settings(TOKEN, "ddddd", &settings()).await;
+
+        assert!(!has_settings(&cache, TOKEN, "aaaaa").await);
+        assert!(!has_settings(&cache, None, "bbbbb").await);
+        assert!(has_settings(&cache, Some("other token"), "ccccc").await);
+        assert!(has_settings(&cache, TOKEN, "ddddd").await);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, HashSet};
+use crate::models::{Group, Member, MemberOrId, System};
+use crate::traits::notifier::Entity;
+use super::entry::update_at;
+use super::index::Container;
+use super::CachePartition;
+
+fn replace_members(members: &mut [Member], updated: &HashMap<&str, &Member>) -> bool {
+    let mut changed = false;
+
+    for member in members.iter_mut() {
+        if let Some(&update) = updated.get(member.id.as_str()) {
+            if member != update {
+                *member = update.clone();
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+fn replace_group_members(groups: &mut [Group], updated: &HashMap<&str, &Member>) -> bool {
+    groups.iter_mut().fold(false, |changed, group| {
+        group.members.as_mut().is_some_and(|members| replace_members(members, updated)) | changed
+    })
+}
+
+fn replace_groups(groups: &mut [Group], updated: &HashMap<&str, &Group>) -> bool {
+    let mut changed = false;
+
+    for group in groups.iter_mut() {
+        if let Some(&update) = updated.get(group.id.as_str()) {
+            // A group fetched without its members doesn't say anything about them
+            let members = update.members.clone().or_else(|| group.members.take());
+            let update = Group { members, ..update.clone() };
+
+            if *group != update {
+                *group = update;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+// Lists embed full copies of the entities, they are updated along with the entities themselves
+// so no answer is older than another, without refreshing the age of the lists.
+// Only the entries the index lists as embedding the entities are visited
+impl CachePartition {
+    pub(super) fn propagate_members(&mut self, members: &[Member]) {
+        let updated: HashMap<&str, &Member> = members.iter().map(|member| (member.id.as_str(), member)).collect();
+        let containers: HashSet<(Container, String)> = members.iter().flat_map(|member| self.containers_owned(Entity::Member, &member.id)).collect();
+
+        for (container, key) in containers {
+            let key = key.as_str();
+
+            match container {
+                Container::SystemMembers => update_at(&mut self.system_members, &mut self.usage, key, |members| replace_members(members, &updated)),
+                Container::GroupMembers => update_at(&mut self.group_members, &mut self.usage, key, |members| replace_members(members, &updated)),
+                Container::Group => update_at(&mut self.groups, &mut self.usage, key, |group| replace_group_members(std::slice::from_mut(group), &updated)),
+                Container::SystemGroups => update_at(&mut self.system_groups, &mut self.usage, key, |groups| replace_group_members(groups, &updated)),
+                Container::MemberGroups => update_at(&mut self.member_groups, &mut self.usage, key, |groups| replace_group_members(groups, &updated)),
+                Container::SystemFronters => update_at(&mut self.system_fronters, &mut self.usage, key, |switch| {
+                    switch.members.iter_mut().fold(false, |changed, member| match member {
+                        MemberOrId::Member(member) => replace_members(std::slice::from_mut(member), &updated) | changed,
+                        MemberOrId::Id(_) => changed,
+                    })
+                }),
+                Container::Message => update_at(&mut self.messages, &mut self.usage, key, |message| {
+                    message.member.as_mut().is_some_and(|member| replace_members(std::slice::from_mut(member), &updated))
+                }),
+            }
+        }
+    }
+
+    pub(super) fn propagate_groups(&mut self, groups: &[Group]) {
+        let updated: HashMap<&str, &Group> = groups.iter().map(|group| (group.id.as_str(), group)).collect();
+        let containers: HashSet<(Container, String)> = groups.iter().flat_map(|group| self.containers_owned(Entity::Group, &group.id)).collect();
+
+        for (container, key) in containers {
+            match container {
+                Container::SystemGroups => update_at(&mut self.system_groups, &mut self.usage, key.as_str(), |groups| replace_groups(groups, &updated)),
+                Container::MemberGroups => update_at(&mut self.member_groups, &mut self.usage, key.as_str(), |groups| replace_groups(groups, &updated)),
+                _ => continue,
+            }
+
+            // Groups fetched with their members embed them from now on
+            self.index(container, &key);
+        }
+    }
+
+    pub(super) fn propagate_system(&mut self, system: &System) {
+        for (container, key) in self.containers_owned(Entity::System, &system.id) {
+            if container != Container::Message {
+                continue;
+            }
+
+            update_at(&mut self.messages, &mut self.usage, key.as_str(), |message| match message.system.as_mut() {
+                Some(embedded) if embedded.id == system.id && embedded != system => {
+                    *embedded = system.clone();
+                    true
+                }
+                _ => false,
+            });
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::models::{Group, Member};
+use crate::traits::notifier::Entity;
+use super::entry::{forget_keys_where, update_at};
+use super::index::Container;
+use super::{contains_group, contains_member, CachePartition};
+
+// Membership is cached from both sides, `group_members` for each group and `member_groups` for each member.
+// A change on either side drops whatever disagrees with it on the other. Only entries which embed one of
+// the two sides can disagree, the index and the new list itself say where they are
+impl CachePartition {
+    fn embedding(&self, entity: Entity, id: &str, container: Container) -> Vec<String> {
+        self.containers(entity, id)
+            .filter(|(candidate, _)| *candidate == container)
+            .map(|(_, key)| key.to_string())
+            .collect()
+    }
+
+    // Member group lists that disagree with the new member list of the group are dropped
+    pub(super) fn reconcile_group_members(&mut self, group: &str, members: &[Member]) {
+        let mut candidates = self.embedding(Entity::Group, group, Container::MemberGroups);
+        candidates.extend(members.iter().map(|member| member.id.clone()));
+
+        forget_keys_where(&mut self.member_groups, &mut self.usage, candidates, |member, groups| contains_group(groups, group) != contains_member(members, member));
+
+        let replace = |target: &mut Group| {
+            if target.id != group || target.members.as_deref().is_none_or(|current| current == members) {
+                return false;
+            }
+
+            target.members = Some(members.to_vec());
+            true
+        };
+
+        update_at(&mut self.groups, &mut self.usage, group, replace);
+        self.index(Container::Group, group);
+
+        for system in self.embedding(Entity::Group, group, Container::SystemGroups) {
+            update_at(&mut self.system_groups, &mut self.usage, system.as_str(), |groups| groups.iter_mut().fold(false, |changed, target| replace(target) | changed));
+            self.index(Container::SystemGroups, &system);
+        }
+    }
+
+    // Group member lists that disagree with the new group list of the member are dropped
+    pub(super) fn reconcile_member_groups(&mut self, member: &str, groups: &[Group]) {
+        let listed = || groups.iter().map(|group| group.id.clone());
+
+        let mut candidates = self.embedding(Entity::Member, member, Container::GroupMembers);
+        candidates.extend(listed());
+
+        forget_keys_where(&mut self.group_members, &mut self.usage, candidates, |group, members| contains_member(members, member) != contains_group(groups, group));
+
+        let disagrees = |target: &Group| target.members.as_deref().is_some_and(|members| contains_member(members, member) != contains_group(groups, &target.id));
+
+        let mut candidates = self.embedding(Entity::Member, member, Container::Group);
+        candidates.extend(listed());
+
+        forget_keys_where(&mut self.groups, &mut self.usage, candidates, |_, target| disagrees(target));
+
+        let mut candidates = self.embedding(Entity::Member, member, Container::SystemGroups);
+        for group in groups {
+            candidates.extend(self.embedding(Entity::Group, &group.id, Container::SystemGroups));
+        }
+
+        forget_keys_where(&mut self.system_groups, &mut self.usage, candidates, |_, targets| targets.iter().any(disagrees));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use super::entry::Usage;
+use super::{CachePartition, InMemoryCache};
+
+// Bumped whenever the layout of the cache changes, snapshots of other versions are discarded
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    partitions: Vec<(&'a Option<String>, &'a CachePartition)>,
+}
+
+// A copy of the cache, taken while holding its lock and written once it has been released
+#[derive(Deserialize)]
+pub(crate) struct Snapshot {
+    partitions: Vec<(Option<String>, CachePartition)>,
+}
+
+impl Snapshot {
+    // Written next to the target first so a crash mid-write never leaves a truncated snapshot behind.
+    // Both the file and the rename are synced before returning
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            partitions: self.partitions.iter().map(|(token, partition)| (token, partition)).collect(),
+        };
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&temporary, path)?;
+
+        let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(directory)?.sync_all()
+    }
+}
+
+impl InMemoryCache {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            partitions: self.partitions.iter().map(|(token, partition)| (token.clone(), partition.clone())).collect(),
+        }
+    }
+
+    // Returns whether a snapshot was loaded, a missing file or one from another version isn't an error
+    pub fn load_snapshot(&mut self, path: &Path) -> io::Result<bool> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+
+        let header: Header = serde_json::from_slice(&contents)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Ok(false);
+        }
+
+        let snapshot: Snapshot = serde_json::from_slice(&contents)?;
+        let mut partitions = HashMap::with_capacity(snapshot.partitions.len());
+
+        for (token, mut partition) in snapshot.partitions {
+            let mut usage = Usage::default();
+            for (map, _) in partition.maps_mut(&self.ttls) {
+                usage.add(map.usage());
+            }
+
+            partition.usage = usage;
+            partition.rebuild_index();
+            partitions.insert(token, partition);
+        }
+
+        self.partitions = partitions;
+        self.enforce_limits();
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serde_json::json;
+    use crate::implementations::in_memory_cache::{CacheLimits, CacheTtls};
+    use crate::models::System;
+    use crate::traits::notifier::{Entity, Notifier};
+    use crate::traits::provider::{Provider, ProviderResult};
+    use super::*;
+
+    const TOKEN: Option<&str> = Some("owner token");
+
+    fn cache() -> InMemoryCache {
+        InMemoryCache::new(CacheTtls::default(), CacheLimits::default())
+    }
+
+    fn system() -> System {
+        serde_json::from_value(json!({ "id": "sysid", "uuid": "00000000-0000-0000-0000-000000000001" })).unwrap()
+    }
+
+    // Unique per test, they run in parallel
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pluralcache-{}-{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_restored() {
+        let path = path("restored");
+        let mut original = cache();
+        original.notify_system(TOKEN, &system()).await;
+        original.notify_alias(TOKEN, Entity::System, "@me", "sysid").await;
+
+        original.snapshot().save(&path).unwrap();
+        let mut restored = cache();
+        let loaded = restored.load_snapshot(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(loaded.unwrap());
+        assert_eq!(restored.get_system(TOKEN, "@me").await, ProviderResult::Ok(system()));
+        assert_eq!(restored.get_system(None, "sysid").await, ProviderResult::Miss);
+    }
+
+    #[tokio::test]
+    async fn snapshots_of_another_version_are_discarded() {
+        let path = path("version");
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system()).await;
+        fs::write(&path, json!({ "version": SNAPSHOT_VERSION + 1, "partitions": [] }).to_string()).unwrap();
+
+        let loaded = cache.load_snapshot(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(!loaded.unwrap());
+        assert_eq!(cache.get_system(TOKEN, "sysid").await, ProviderResult::Ok(system()));
+    }
+
+    #[test]
+    fn missing_snapshots_are_not_an_error() {
+        assert!(!cache().load_snapshot(&path("missing")).unwrap());
+    }
+}
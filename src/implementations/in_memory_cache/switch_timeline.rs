@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::models::{MemberOrId, Switch};
+use crate::traits::notifier::notified_age;
+
+// PluralKit never returns more switches than this, whatever the limit asked for
+pub(crate) const MAX_PAGE_SIZE: u64 = 100;
+
+pub(super) fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+pub(super) fn remove_switch_member(switch: &mut Switch, id: &str) -> bool {
+    let before = switch.members.len();
+    switch.members.retain(|member| match member {
+        MemberOrId::Member(member) => member.id != id,
+        MemberOrId::Id(member) => member != id,
+    });
+
+    switch.members.len() != before
+}
+
+// A range of the history in which every switch is known, a missing start means the beginning of the history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Window {
+    start: Option<DateTime<Utc>>,
+    end: DateTime<Utc>,
+}
+
+impl Window {
+    fn touches(&self, other: &Window) -> bool {
+        let starts_before_end = |window: &Window, end: DateTime<Utc>| window.start.is_none_or(|start| start <= end);
+
+        starts_before_end(self, other.end) && starts_before_end(other, self.end)
+    }
+
+    fn merge(&self, other: &Window) -> Window {
+        let start = match (self.start, other.start) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            _ => None,
+        };
+
+        Window {
+            start,
+            end: self.end.max(other.end),
+        }
+    }
+
+    fn covers(&self, end: DateTime<Utc>) -> bool {
+        self.start.is_none_or(|start| start <= end) && end <= self.end
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(super) struct SwitchTimeline {
+    switches: BTreeMap<(DateTime<Utc>, String), Switch>,
+    windows: Vec<Window>,
+    // When the latest page was fetched, used to answer requests without a `before`
+    head_fetched_at: Option<DateTime<Utc>>,
+}
+
+impl SwitchTimeline {
+    fn insert(&mut self, switch: &Switch) {
+        if let Some(timestamp) = parse_timestamp(&switch.timestamp) {
+            self.switches.insert((timestamp, switch.id.clone()), switch.clone());
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let before = self.switches.len();
+        self.switches.retain(|(_, switch_id), _| switch_id != id);
+
+        self.switches.len() != before
+    }
+
+    pub fn remove_member(&mut self, id: &str) -> bool {
+        let mut changed = false;
+
+        for switch in self.switches.values_mut() {
+            changed |= remove_switch_member(switch, id);
+        }
+
+        changed
+    }
+
+    // Records a page of switches as returned by PluralKit, newest first and strictly older than `before`
+    pub fn merge_page(&mut self, before: &str, limit: u64, switches: &[Switch]) {
+        let end = if before.is_empty() {
+            let fetched_at = Utc::now() - chrono::Duration::from_std(notified_age()).unwrap_or_default();
+            self.head_fetched_at = Some(fetched_at);
+            fetched_at
+        } else {
+            match parse_timestamp(before) {
+                Some(end) => end,
+                None => return,
+            }
+        };
+
+        for switch in switches {
+            self.insert(switch);
+        }
+
+        // A page shorter than what PluralKit could have returned reaches the beginning of the history
+        let start = if (switches.len() as u64) < limit.min(MAX_PAGE_SIZE) {
+            None
+        } else {
+            match switches.iter().filter_map(|switch| parse_timestamp(&switch.timestamp)).min() {
+                Some(start) => Some(start),
+                None => return,
+            }
+        };
+
+        let mut window = Window { start, end };
+        while let Some(index) = self.windows.iter().position(|other| window.touches(other)) {
+            window = window.merge(&self.windows.swap_remove(index));
+        }
+        self.windows.push(window);
+    }
+
+    // Answers a page request only if the requested range lies in a known-contiguous window
+    pub fn page(&self, before: &str, limit: u64, head_ttl: Duration) -> Option<Vec<Switch>> {
+        let (end, upper) = if before.is_empty() {
+            let head_fetched_at = self.head_fetched_at?;
+            let age = (Utc::now() - head_fetched_at).to_std().unwrap_or_default();
+
+            if age >= head_ttl {
+                return None;
+            }
+
+            (head_fetched_at, None)
+        } else {
+            let before = parse_timestamp(before)?;
+            (before, Some(before))
+        };
+
+        let window = self.windows.iter().find(|window| window.covers(end))?;
+        let page: Vec<Switch> = self.switches
+            .iter()
+            .rev()
+            .filter(|((timestamp, _), _)| upper.is_none_or(|upper| *timestamp < upper))
+            .take_while(|((timestamp, _), _)| window.start.is_none_or(|start| *timestamp >= start))
+            .take(limit as usize)
+            .map(|(_, switch)| switch.clone())
+            .collect();
+
+        if page.len() as u64 == limit || window.start.is_none() {
+            Some(page)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD_TTL: Duration = Duration::from_secs(60);
+
+    fn timestamp(day: u32) -> String {
+        format!("2024-01-{day:02}T00:00:00Z")
+    }
+
+    fn switch(day: u32) -> Switch {
+        Switch {
+            id: format!("switch-{day}"),
+            timestamp: timestamp(day),
+            members: vec![MemberOrId::Id("abcde".to_string())],
+        }
+    }
+
+    // Newest first, as PluralKit returns them
+    fn switches(days: impl DoubleEndedIterator<Item = u32>) -> Vec<Switch> {
+        days.rev().map(switch).collect()
+    }
+
+    #[test]
+    fn unknown_pages_are_not_answered() {
+        let timeline = SwitchTimeline::default();
+
+        assert_eq!(timeline.page(&timestamp(10), 3, HEAD_TTL), None);
+        assert_eq!(timeline.page("", 3, HEAD_TTL), None);
+    }
+
+    #[test]
+    fn full_page_answers_requests_inside_it() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page(&timestamp(10), 3, &switches(7..=9));
+
+        assert_eq!(timeline.page(&timestamp(10), 3, HEAD_TTL), Some(switches(7..=9)));
+        assert_eq!(timeline.page(&timestamp(9), 2, HEAD_TTL), Some(switches(7..=8)));
+        // Older switches may exist before the page's oldest one
+        assert_eq!(timeline.page(&timestamp(10), 4, HEAD_TTL), None);
+        // Newer switches may exist after the page's `before`
+        assert_eq!(timeline.page(&timestamp(11), 3, HEAD_TTL), None);
+    }
+
+    #[test]
+    fn short_page_reaches_the_beginning_of_the_history() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page(&timestamp(10), 5, &switches(1..=2));
+
+        assert_eq!(timeline.page(&timestamp(10), 10, HEAD_TTL), Some(switches(1..=2)));
+        assert_eq!(timeline.page(&timestamp(2), 10, HEAD_TTL), Some(switches(1..=1)));
+        assert_eq!(timeline.page(&timestamp(1), 10, HEAD_TTL), Some(Vec::new()));
+    }
+
+    #[test]
+    fn limits_above_the_maximum_page_size_are_not_short_pages() {
+        let mut timeline = SwitchTimeline::default();
+        let page: Vec<Switch> = (0..MAX_PAGE_SIZE)
+            .map(|minute| Switch {
+                id: format!("switch-{minute}"),
+                timestamp: format!("2024-01-01T{:02}:{:02}:00Z", minute / 60, minute % 60),
+                members: Vec::new(),
+            })
+            .rev()
+            .collect();
+        timeline.merge_page(&timestamp(2), 1000, &page);
+
+        assert_eq!(timeline.page(&timestamp(2), MAX_PAGE_SIZE, HEAD_TTL), Some(page));
+        assert_eq!(timeline.page(&timestamp(2), MAX_PAGE_SIZE + 1, HEAD_TTL), None);
+    }
+
+    #[test]
+    fn overlapping_pages_are_merged() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page(&timestamp(10), 3, &switches(7..=9));
+        timeline.merge_page(&timestamp(7), 3, &switches(4..=6));
+
+        assert_eq!(timeline.page(&timestamp(10), 6, HEAD_TTL), Some(switches(4..=9)));
+        assert_eq!(timeline.page(&timestamp(8), 3, HEAD_TTL), Some(switches(5..=7)));
+    }
+
+    #[test]
+    fn pages_with_a_gap_are_not_merged() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page(&timestamp(10), 3, &switches(7..=9));
+        timeline.merge_page(&timestamp(5), 3, &switches(2..=4));
+
+        assert_eq!(timeline.page(&timestamp(10), 6, HEAD_TTL), None);
+        assert_eq!(timeline.page(&timestamp(5), 3, HEAD_TTL), Some(switches(2..=4)));
+    }
+
+    #[test]
+    fn head_page_expires_after_its_ttl() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page("", 3, &switches(7..=9));
+
+        assert_eq!(timeline.page("", 3, HEAD_TTL), Some(switches(7..=9)));
+        assert_eq!(timeline.page("", 3, Duration::ZERO), None);
+        // Pages with a `before` don't depend on the head being fresh
+        assert_eq!(timeline.page(&timestamp(9), 2, Duration::ZERO), Some(switches(7..=8)));
+    }
+
+    #[test]
+    fn removed_switches_and_members_are_left_out_of_pages() {
+        let mut timeline = SwitchTimeline::default();
+        timeline.merge_page(&timestamp(10), 5, &switches(1..=3));
+
+        assert!(timeline.remove("switch-2"));
+        assert!(!timeline.remove("switch-2"));
+        assert!(timeline.remove_member("abcde"));
+
+        let page = timeline.page(&timestamp(10), 5, HEAD_TTL).unwrap();
+        assert_eq!(page.iter().map(|switch| switch.id.as_str()).collect::<Vec<_>>(), ["switch-3", "switch-1"]);
+        assert!(page.iter().all(|switch| switch.members.is_empty()));
+    }
+}
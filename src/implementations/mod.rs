@@ -1,3 +1,10 @@
 pub(crate) mod in_memory_cache;
 pub(crate) mod origin_api;
-pub(crate) mod controller;
\ No newline at end of file
+pub(crate) mod sqlite_cache;
+pub(crate) mod controller;
+use sha2::{Digest, Sha256};
+
+// Tokens are never kept in clear by the caches, only their hash
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,280 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CircuitBreakerPolicy {
+    // Consecutive failed requests before the circuit opens
+    pub failure_threshold: u32,
+    // How long requests fail fast before a probe is let through
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub open_for: Duration,
+    // Consecutive successful probes needed to close the circuit again
+    pub probe_successes: u32,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+            probe_successes: 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub times_opened: u64,
+    // Milliseconds until the next probe while open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_in: Option<u64>,
+}
+
+struct Circuit {
+    state: CircuitState,
+    failures: u32,
+    successes: u32,
+    opened_at: Instant,
+    times_opened: u64,
+    // Only one probe is sent at a time while half-open, everything else keeps failing fast
+    probe_in_flight: bool,
+}
+
+// Cloned for the status endpoint, which reads it without going through the origin
+#[derive(Clone)]
+pub(crate) struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: CircuitBreakerPolicy) -> Self {
+        let circuit = Circuit {
+            state: CircuitState::Closed,
+            failures: 0,
+            successes: 0,
+            opened_at: Instant::now(),
+            times_opened: 0,
+            probe_in_flight: false,
+        };
+
+        Self {
+            policy,
+            circuit: Arc::new(Mutex::new(circuit)),
+        }
+    }
+
+    fn open(&self, circuit: &mut Circuit) {
+        circuit.state = CircuitState::Open;
+        circuit.opened_at = Instant::now();
+        circuit.times_opened += 1;
+        circuit.probe_in_flight = false;
+
+        eprintln!("Origin circuit opened after {} consecutive failures", circuit.failures);
+    }
+
+    // Whether a request may be sent. Once the circuit has been open long enough the first one becomes a probe,
+    // and the next probe is only let through once its outcome is known
+    pub fn allow(&self) -> Option<Permit<'_>> {
+        let mut circuit = self.circuit.lock().unwrap();
+
+        let probe = match circuit.state {
+            CircuitState::Closed => false,
+            CircuitState::HalfOpen if !circuit.probe_in_flight => true,
+            CircuitState::Open if circuit.opened_at.elapsed() >= self.policy.open_for => {
+                circuit.state = CircuitState::HalfOpen;
+                circuit.successes = 0;
+                true
+            }
+            CircuitState::HalfOpen | CircuitState::Open => return None,
+        };
+
+        circuit.probe_in_flight |= probe;
+        Some(Permit { breaker: self, probe })
+    }
+
+    fn record_success(&self, probe: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.failures = 0;
+        circuit.probe_in_flight &= !probe;
+
+        if circuit.state == CircuitState::HalfOpen {
+            circuit.successes += 1;
+
+            if circuit.successes >= self.policy.probe_successes {
+                circuit.state = CircuitState::Closed;
+                eprintln!("Origin circuit closed after {} successful probes", circuit.successes);
+            }
+        }
+    }
+
+    // A failed probe reopens the circuit right away
+    fn record_failure(&self, probe: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.failures += 1;
+        circuit.probe_in_flight &= !probe;
+
+        match circuit.state {
+            CircuitState::Closed if circuit.failures >= self.policy.failure_threshold => self.open(&mut circuit),
+            CircuitState::HalfOpen => self.open(&mut circuit),
+            _ => {}
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let circuit = self.circuit.lock().unwrap();
+
+        let probe_in = match circuit.state {
+            CircuitState::Open => Some(self.policy.open_for.saturating_sub(circuit.opened_at.elapsed()).as_millis() as u64),
+            _ => None,
+        };
+
+        CircuitStatus {
+            state: circuit.state,
+            consecutive_failures: circuit.failures,
+            times_opened: circuit.times_opened,
+            probe_in,
+        }
+    }
+}
+
+// A request let through by the breaker. Dropping it without recording an outcome, because the request was rate
+// limited or cancelled, lets the next probe through without counting for or against the origin
+pub(crate) struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Permit<'_> {
+    pub fn record_success(mut self) {
+        self.breaker.record_success(std::mem::take(&mut self.probe));
+    }
+
+    pub fn record_failure(mut self) {
+        self.breaker.record_failure(std::mem::take(&mut self.probe));
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.circuit.lock().unwrap().probe_in_flight = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_for: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerPolicy {
+            failure_threshold: 2,
+            open_for,
+            probe_successes: 2,
+        })
+    }
+
+    fn open(breaker: &CircuitBreaker) {
+        breaker.allow().unwrap().record_failure();
+        breaker.allow().unwrap().record_failure();
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(30));
+        breaker.allow().unwrap().record_failure();
+
+        assert!(breaker.allow().is_some());
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+
+        breaker.allow().unwrap().record_failure();
+        let status = breaker.status();
+
+        assert!(breaker.allow().is_none());
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.times_opened, 1);
+        assert!(status.probe_in.is_some_and(|probe_in| probe_in > 29_000));
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = breaker(Duration::from_secs(30));
+        breaker.allow().unwrap().record_failure();
+        breaker.allow().unwrap().record_success();
+        breaker.allow().unwrap().record_failure();
+
+        assert!(breaker.allow().is_some());
+        assert_eq!(breaker.status().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn closes_after_enough_successful_probes() {
+        let breaker = breaker(Duration::ZERO);
+        open(&breaker);
+
+        let probe = breaker.allow().unwrap();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert_eq!(breaker.status().probe_in, None);
+
+        probe.record_success();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+
+        breaker.allow().unwrap().record_success();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn only_one_probe_is_in_flight() {
+        let breaker = breaker(Duration::ZERO);
+        open(&breaker);
+
+        let probe = breaker.allow().unwrap();
+        assert!(breaker.allow().is_none());
+
+        probe.record_success();
+        assert!(breaker.allow().is_some());
+    }
+
+    #[test]
+    fn rate_limited_probe_lets_the_next_one_through() {
+        let breaker = breaker(Duration::ZERO);
+        open(&breaker);
+
+        // A rate limited probe says nothing about the origin's health
+        let probe = breaker.allow().unwrap();
+        assert!(breaker.allow().is_none());
+        drop(probe);
+
+        let status = breaker.status();
+        assert_eq!(status.state, CircuitState::HalfOpen);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(breaker.allow().is_some());
+    }
+
+    #[test]
+    fn failed_probe_reopens_right_away() {
+        let breaker = breaker(Duration::ZERO);
+        open(&breaker);
+        breaker.allow().unwrap().record_success();
+
+        breaker.allow().unwrap().record_failure();
+        let status = breaker.status();
+
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.times_opened, 2);
+    }
+}
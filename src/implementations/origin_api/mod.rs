@@ -0,0 +1,422 @@
+mod circuit_breaker;
+mod rate_limit;
+
+use std::sync::Mutex;
+use std::time::Duration;
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::header::{AUTHORIZATION, RETRY_AFTER};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use crate::models::{AutoproxySettings, Group, Member, MemberGuildSettings, Message, Switch, System, SystemGuildSettings, SystemSettings};
+use crate::traits::provider::{Failure, Provider, ProviderResult};
+use rate_limit::RateLimiter;
+
+pub(crate) use circuit_breaker::{CircuitBreaker, CircuitBreakerPolicy, CircuitStatus};
+pub(crate) use rate_limit::RateLimitPolicy;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RetryPolicy {
+    // Including the first attempt
+    pub max_attempts: u32,
+    // Each timeout already waited for the whole request timeout, so they get fewer attempts
+    pub max_timeout_attempts: u32,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub base_delay: Duration,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub max_delay: Duration,
+    // Share of requests that may be retried, so an outage doesn't multiply the load on PluralKit
+    pub budget_ratio: f64,
+    pub max_budget: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            max_timeout_attempts: 2,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            budget_ratio: 0.1,
+            max_budget: 10.0,
+        }
+    }
+}
+
+// Failures that may succeed if the request is sent again
+#[derive(Clone, Debug, PartialEq)]
+enum Transient {
+    Timeout,
+    Connection(String),
+    Status(StatusCode, Option<Duration>),
+}
+
+impl From<Transient> for Failure {
+    fn from(transient: Transient) -> Self {
+        match transient {
+            Transient::Timeout => Failure::Timeout,
+            Transient::Connection(error) => Failure::Transport(error),
+            Transient::Status(status, _) => Failure::Status(status.as_u16()),
+        }
+    }
+}
+
+// Failures which mean PluralKit itself is unwell, as opposed to requests it refused or we couldn't read
+fn is_outage(failure: &Failure) -> bool {
+    match failure {
+        Failure::Timeout | Failure::Transport(_) => true,
+        Failure::Status(status) | Failure::Origin { status, .. } => *status >= 500,
+        _ => false,
+    }
+}
+
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?.parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+#[derive(Deserialize)]
+struct OriginError {
+    code: u32,
+    message: String,
+}
+
+async fn origin_failure(response: reqwest::Response) -> Failure {
+    let status = response.status().as_u16();
+
+    match response.json::<OriginError>().await {
+        Ok(error) => Failure::Origin { status, code: error.code, message: error.message },
+        Err(_) => Failure::Status(status),
+    }
+}
+
+// Body of PluralKit's 429 responses, the delay is in milliseconds
+#[derive(Deserialize)]
+struct RateLimited {
+    retry_after: u64,
+}
+
+// Shared by every request without a lock around it, the state requests share is only locked to be updated
+// and never while waiting on the network or sleeping
+pub(crate) struct OriginApi {
+    client: reqwest::Client,
+    base_url: Url,
+    retry_policy: RetryPolicy,
+    retry_budget: Mutex<f64>,
+    rate_limiter: Mutex<RateLimiter>,
+    circuit_breaker: CircuitBreaker,
+}
+
+impl OriginApi {
+    async fn attempt<T: for<'de> Deserialize<'de>>(&self, token: Option<&str>, url: &Url) -> Result<ProviderResult<T>, Transient> {
+        let acquired = self.rate_limiter.lock().unwrap().acquire();
+        match acquired {
+            Ok(wait) if wait.is_zero() => {}
+            Ok(wait) => tokio::time::sleep(wait).await,
+            Err(wait) => return Ok(ProviderResult::RateLimited(wait)),
+        }
+
+        let mut request = self.client.get(url.clone());
+
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, token);
+        }
+
+        let transient = |error: reqwest::Error| {
+            if error.is_timeout() {
+                Err(Transient::Timeout)
+            } else if error.is_connect() || error.is_request() {
+                Err(Transient::Connection(error.to_string()))
+            } else {
+                Ok(ProviderResult::Failed(Failure::Transport(error.to_string())))
+            }
+        };
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(error) => return transient(error),
+        };
+
+        self.rate_limiter.lock().unwrap().observe(response.headers());
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let header = retry_after(&response).or_else(|| rate_limit::reset_after(response.headers()));
+            let body = response.json::<RateLimited>().await.ok().map(|body| Duration::from_millis(body.retry_after));
+            let mut rate_limiter = self.rate_limiter.lock().unwrap();
+            let retry_after = header.or(body).unwrap_or(rate_limiter.policy().period);
+
+            rate_limiter.throttled(retry_after);
+            return Ok(ProviderResult::RateLimited(retry_after));
+        } else if [StatusCode::BAD_GATEWAY, StatusCode::SERVICE_UNAVAILABLE, StatusCode::GATEWAY_TIMEOUT].contains(&status) {
+            return Err(Transient::Status(status, retry_after(&response)));
+        } else if status == StatusCode::NOT_FOUND {
+            return Ok(ProviderResult::NotFound);
+        } else if [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN].contains(&status) {
+            return Ok(ProviderResult::Unauthorized);
+        } else if !status.is_success() {
+            return Ok(ProviderResult::Failed(origin_failure(response).await));
+        }
+
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(error) => return transient(error),
+        };
+
+        match serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_slice(&body)) {
+            Ok(json) => Ok(ProviderResult::Ok(json)),
+            Err(error) => Ok(ProviderResult::Failed(Failure::Decode { path: error.path().to_string(), message: error.inner().to_string() })),
+        }
+    }
+
+    // Full jitter, a random delay up to an exponentially growing cap
+    fn backoff(&self, attempt: u32, transient: &Transient) -> Option<Duration> {
+        let cap = self.retry_policy.base_delay.saturating_mul(1 << (attempt - 1).min(16)).min(self.retry_policy.max_delay);
+
+        match transient {
+            Transient::Status(_, Some(retry_after)) if *retry_after > self.retry_policy.max_delay => None,
+            Transient::Status(_, Some(retry_after)) => Some(*retry_after),
+            _ => Some(cap.mul_f64(rand::thread_rng().gen::<f64>())),
+        }
+    }
+
+    fn withdraw_retry(&self) -> bool {
+        let mut retry_budget = self.retry_budget.lock().unwrap();
+
+        if *retry_budget < 1.0 {
+            return false;
+        }
+
+        *retry_budget -= 1.0;
+        true
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(&self, token: Option<&str>, url: Url) -> ProviderResult<T> {
+        {
+            let mut retry_budget = self.retry_budget.lock().unwrap();
+            *retry_budget = (*retry_budget + self.retry_policy.budget_ratio).min(self.retry_policy.max_budget);
+        }
+
+        let mut attempt = 1;
+        loop {
+            let transient = match self.attempt(token, &url).await {
+                Ok(result) => return result,
+                Err(transient) => transient,
+            };
+
+            let max_attempts = match transient {
+                Transient::Timeout => self.retry_policy.max_timeout_attempts,
+                _ => self.retry_policy.max_attempts,
+            };
+
+            let delay = match self.backoff(attempt, &transient) {
+                Some(delay) if attempt < max_attempts && self.withdraw_retry() => delay,
+                _ => return ProviderResult::Failed(transient.into()),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Fails fast while the circuit is open, the controller then falls back to stale data if it has any
+    async fn get<T: for<'de> Deserialize<'de>>(&self, token: Option<&str>, url: Url) -> ProviderResult<T> {
+        let permit = match self.circuit_breaker.allow() {
+            Some(permit) => permit,
+            None => return ProviderResult::Failed(Failure::CircuitOpen),
+        };
+
+        let result = self.send(token, url).await;
+
+        // Rate limited requests never reached the origin, dropping the permit records nothing
+        match &result {
+            ProviderResult::Failed(failure) if is_outage(failure) => permit.record_failure(),
+            ProviderResult::RateLimited(_) => drop(permit),
+            _ => permit.record_success(),
+        }
+
+        result
+    }
+
+    // IDs come from our own clients, every part of the URL is percent-encoded rather than pasted in
+    fn url(&self, segments: &[&str], query: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut().expect("http URLs always have a path").pop_if_empty().extend(segments);
+
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        url
+    }
+
+    // The URL is validated with the rest of the configuration
+    pub fn new(base_url: &str, timeout: Duration, connect_timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent("pluralcache")
+            .gzip(true)
+            .brotli(true)
+            .timeout(timeout)
+            .connect_timeout(connect_timeout)
+            .build()
+            .unwrap();
+
+        let retry_policy = RetryPolicy::default();
+
+        Self {
+            client,
+            base_url: Url::parse(base_url).expect("invalid origin URL"),
+            retry_budget: Mutex::new(retry_policy.max_budget),
+            retry_policy,
+            rate_limiter: Mutex::new(RateLimiter::new(RateLimitPolicy::default())),
+            circuit_breaker: CircuitBreaker::new(CircuitBreakerPolicy::default()),
+        }
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_budget = Mutex::new(retry_policy.max_budget);
+        self.retry_policy = retry_policy;
+    }
+
+    pub fn set_rate_limit_policy(&mut self, rate_limit_policy: RateLimitPolicy) {
+        self.rate_limiter = Mutex::new(RateLimiter::new(rate_limit_policy));
+    }
+
+    pub fn set_circuit_breaker_policy(&mut self, circuit_breaker_policy: CircuitBreakerPolicy) {
+        self.circuit_breaker = CircuitBreaker::new(circuit_breaker_policy);
+    }
+
+    pub fn circuit_breaker(&self) -> CircuitBreaker {
+        self.circuit_breaker.clone()
+    }
+}
+
+#[async_trait]
+impl Provider for OriginApi {
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        self.get(token, self.url(&["systems", id], &[])).await
+    }
+
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        self.get(token, self.url(&["systems", id, "settings"], &[])).await
+    }
+
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        self.get(token, self.url(&["systems", id, "guilds", guild], &[])).await
+    }
+
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        self.get(token, self.url(&["systems", id, "autoproxy"], &[])).await
+    }
+
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        self.get(token, self.url(&["systems", id, "members"], &[])).await
+    }
+
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        self.get(token, self.url(&["members", id], &[])).await
+    }
+
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        self.get(token, self.url(&["members", id, "groups"], &[])).await
+    }
+
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        self.get(token, self.url(&["members", id, "guilds", guild], &[])).await
+    }
+
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        self.get(token, self.url(&["systems", id, "groups"], &[("with_members", with_member.to_string().as_str())])).await
+    }
+
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        self.get(token, self.url(&["groups", id], &[])).await
+    }
+
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        self.get(token, self.url(&["groups", id, "members"], &[])).await
+    }
+
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        let limit = limit.to_string();
+        let query = if before.is_empty() {
+            vec![("limit", limit.as_str())]
+        } else {
+            vec![("before", before), ("limit", limit.as_str())]
+        };
+
+        self.get(token, self.url(&["systems", id, "switches"], &query)).await
+    }
+
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        self.get(token, self.url(&["systems", id, "fronters"], &[])).await
+    }
+
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        self.get(token, self.url(&["systems", id, "switches", switch_id], &[])).await
+    }
+
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        self.get(token, self.url(&["messages", id], &[])).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(retry_policy: RetryPolicy) -> OriginApi {
+        let mut origin = OriginApi::new("http://localhost", Duration::from_secs(1), Duration::from_secs(1));
+        origin.set_retry_policy(retry_policy);
+
+        origin
+    }
+
+    #[test]
+    fn backoff_is_jittered_under_an_exponential_cap() {
+        let origin = origin(RetryPolicy::default());
+        let transient = Transient::Connection("refused".to_string());
+
+        for (attempt, cap) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1600), (6, 2000), (40, 2000)] {
+            let delays: Vec<Duration> = (0..100).map(|_| origin.backoff(attempt, &transient).unwrap()).collect();
+
+            assert!(delays.iter().all(|delay| *delay <= Duration::from_millis(cap)), "attempt {attempt}: {delays:?}");
+            assert!(delays.iter().any(|delay| *delay != delays[0]), "attempt {attempt}: {delays:?}");
+        }
+    }
+
+    #[test]
+    fn backoff_honours_retry_after_up_to_the_max_delay() {
+        let origin = origin(RetryPolicy::default());
+        let status = |retry_after| Transient::Status(StatusCode::SERVICE_UNAVAILABLE, Some(retry_after));
+
+        assert_eq!(origin.backoff(1, &status(Duration::from_secs(1))), Some(Duration::from_secs(1)));
+        assert_eq!(origin.backoff(3, &status(Duration::from_secs(2))), Some(Duration::from_secs(2)));
+        assert_eq!(origin.backoff(1, &status(Duration::from_secs(3))), None);
+    }
+
+    #[test]
+    fn retries_stop_once_the_budget_is_spent() {
+        let origin = origin(RetryPolicy { max_budget: 2.5, ..RetryPolicy::default() });
+
+        assert!(origin.withdraw_retry());
+        assert!(origin.withdraw_retry());
+        assert!(!origin.withdraw_retry());
+        assert!(!origin.withdraw_retry());
+    }
+
+    // Every request earns a fraction of a retry, only enough requests make up for a spent budget
+    #[tokio::test]
+    async fn spent_budget_is_refilled_by_later_requests() {
+        let origin = origin(RetryPolicy { max_attempts: 1, budget_ratio: 0.5, max_budget: 1.0, ..RetryPolicy::default() });
+        let url = Url::parse("http://127.0.0.1:9/systems/sysid").unwrap();
+        assert!(origin.withdraw_retry());
+
+        assert!(matches!(origin.send::<System>(None, url.clone()).await, ProviderResult::Failed(_)));
+        assert!(!origin.withdraw_retry());
+
+        assert!(matches!(origin.send::<System>(None, url).await, ProviderResult::Failed(_)));
+        assert!(origin.withdraw_retry());
+    }
+}
@@ -0,0 +1,231 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RateLimitPolicy {
+    // Used until PluralKit reports its own limit
+    pub requests: u32,
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub period: Duration,
+    // Requests which would have to be queued for longer are answered as rate limited right away
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub max_wait: Duration,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        Self {
+            requests: 10,
+            period: Duration::from_secs(1),
+            max_wait: Duration::from_secs(5),
+        }
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<f64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+// PluralKit sends the reset as a Unix timestamp, read as milliseconds when too large to be seconds
+pub(super) fn reset_after(headers: &HeaderMap) -> Option<Duration> {
+    let reset = header(headers, "x-ratelimit-reset")?;
+    let reset = if reset > 1e11 { reset / 1000.0 } else { reset };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs_f64();
+
+    Duration::try_from_secs_f64(reset - now).ok()
+}
+
+// Token bucket refilled at a steady rate and corrected by the headers PluralKit sends back.
+// Tokens go negative while requests are queued, each one waits for its own token to be refilled
+pub(super) struct RateLimiter {
+    policy: RateLimitPolicy,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    // Set once PluralKit reports the bucket as empty, nothing is sent before then
+    blocked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        let capacity = f64::from(policy.requests.max(1));
+
+        Self {
+            policy,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+            blocked_until: None,
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.capacity / self.policy.period.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate()).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    // Takes a token and returns how long to wait before sending, or how long until one would be available
+    // when that's longer than the policy allows
+    pub fn acquire(&mut self) -> Result<Duration, Duration> {
+        let now = Instant::now();
+        self.refill(now);
+
+        let blocked = self.blocked_until.map_or(Duration::ZERO, |until| until.saturating_duration_since(now));
+        let refill = Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / self.rate());
+        let wait = blocked.max(refill);
+
+        if wait > self.policy.max_wait {
+            return Err(wait);
+        }
+
+        self.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    // Our own count can only be lower than PluralKit's, it includes requests it hasn't seen yet
+    pub fn observe(&mut self, headers: &HeaderMap) {
+        let now = Instant::now();
+        self.refill(now);
+
+        if let Some(limit) = header(headers, "x-ratelimit-limit") {
+            self.capacity = limit.max(1.0);
+        }
+
+        if let Some(remaining) = header(headers, "x-ratelimit-remaining") {
+            self.tokens = self.tokens.min(remaining);
+
+            if remaining < 1.0 {
+                if let Some(reset) = reset_after(headers) {
+                    self.blocked_until = Some(now + reset);
+                }
+            }
+        }
+    }
+
+    pub fn throttled(&mut self, retry_after: Duration) {
+        let now = Instant::now();
+        self.refill(now);
+
+        let until = now + retry_after;
+        self.tokens = self.tokens.min(0.0);
+        self.blocked_until = Some(self.blocked_until.map_or(until, |blocked| blocked.max(until)));
+    }
+
+    pub fn policy(&self) -> &RateLimitPolicy {
+        &self.policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use super::*;
+
+    fn limiter(requests: u32, period: Duration) -> RateLimiter {
+        RateLimiter::new(RateLimitPolicy {
+            requests,
+            period,
+            max_wait: Duration::from_secs(5),
+        })
+    }
+
+    fn headers(values: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in values {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+
+        headers
+    }
+
+    fn unix_now() -> f64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
+    }
+
+    #[test]
+    fn requests_within_capacity_are_sent_right_away() {
+        let mut limiter = limiter(2, Duration::from_secs(1));
+
+        assert_eq!(limiter.acquire(), Ok(Duration::ZERO));
+        assert_eq!(limiter.acquire(), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn requests_over_capacity_wait_for_their_token() {
+        let mut limiter = limiter(2, Duration::from_secs(1));
+        limiter.acquire().unwrap();
+        limiter.acquire().unwrap();
+
+        let first = limiter.acquire().unwrap();
+        let second = limiter.acquire().unwrap();
+
+        assert!(first > Duration::from_millis(400) && first <= Duration::from_millis(500), "{first:?}");
+        assert!(second > Duration::from_millis(900) && second <= Duration::from_secs(1), "{second:?}");
+    }
+
+    #[test]
+    fn waits_longer_than_allowed_are_refused() {
+        let mut limiter = limiter(1, Duration::from_secs(10));
+        limiter.acquire().unwrap();
+
+        let wait = limiter.acquire().unwrap_err();
+
+        assert!(wait > Duration::from_secs(9), "{wait:?}");
+        // Refused requests don't take a token
+        assert!(limiter.acquire().is_err());
+        assert!(limiter.tokens > -0.01);
+    }
+
+    #[test]
+    fn reported_limit_replaces_the_policy() {
+        let mut limiter = limiter(10, Duration::from_secs(1));
+        limiter.observe(&headers(&[("x-ratelimit-limit", "2".to_string()), ("x-ratelimit-remaining", "1".to_string())]));
+
+        assert_eq!(limiter.capacity, 2.0);
+        assert_eq!(limiter.acquire(), Ok(Duration::ZERO));
+        assert!(limiter.acquire().unwrap() > Duration::from_millis(400));
+    }
+
+    #[test]
+    fn empty_bucket_blocks_until_the_reset() {
+        let mut limiter = limiter(10, Duration::from_secs(1));
+        let reset = (unix_now() + 3.0).to_string();
+        limiter.observe(&headers(&[("x-ratelimit-remaining", "0".to_string()), ("x-ratelimit-reset", reset)]));
+
+        let wait = limiter.acquire().unwrap();
+
+        assert!(wait > Duration::from_secs(2) && wait <= Duration::from_secs(3), "{wait:?}");
+    }
+
+    #[test]
+    fn throttling_blocks_for_the_retry_delay() {
+        let mut limiter = limiter(10, Duration::from_secs(1));
+        limiter.throttled(Duration::from_secs(2));
+        // A shorter delay doesn't shorten the block
+        limiter.throttled(Duration::from_secs(1));
+
+        let wait = limiter.acquire().unwrap();
+
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2), "{wait:?}");
+    }
+
+    #[test]
+    fn reset_is_read_as_seconds_or_milliseconds() {
+        let seconds = reset_after(&headers(&[("x-ratelimit-reset", (unix_now() + 30.0).to_string())])).unwrap();
+        let milliseconds = reset_after(&headers(&[("x-ratelimit-reset", ((unix_now() + 30.0) * 1000.0).to_string())])).unwrap();
+
+        assert!(seconds > Duration::from_secs(29) && seconds <= Duration::from_secs(30), "{seconds:?}");
+        assert!(milliseconds > Duration::from_secs(29) && milliseconds <= Duration::from_secs(30), "{milliseconds:?}");
+        assert_eq!(reset_after(&headers(&[("x-ratelimit-reset", (unix_now() - 30.0).to_string())])), None);
+        assert_eq!(reset_after(&HeaderMap::new()), None);
+    }
+}
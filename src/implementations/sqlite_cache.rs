@@ -0,0 +1,912 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::implementations::hash_token;
+use crate::implementations::in_memory_cache::CacheTtls;
+use crate::models::{AutoproxySettings, Group, Member, MemberGuildSettings, MemberOrId, Message, Switch, System, SystemGuildSettings, SystemSettings};
+use crate::traits::provider::{Failure, Provider, ProviderResult};
+use crate::traits::notifier::{notified_age, Entity, Notifier};
+
+const SYSTEM: &str = "system";
+const SYSTEM_SETTINGS: &str = "system_settings";
+const SYSTEM_GUILD_SETTINGS: &str = "system_guild_settings";
+const SYSTEM_AUTOPROXY: &str = "system_autoproxy";
+const SYSTEM_MEMBERS: &str = "system_members";
+const MEMBER: &str = "member";
+const MEMBER_GROUPS: &str = "member_groups";
+const MEMBER_GUILD_SETTINGS: &str = "member_guild_settings";
+const SYSTEM_GROUPS: &str = "system_groups";
+const GROUP: &str = "group";
+const GROUP_MEMBERS: &str = "group_members";
+const SYSTEM_SWITCHES: &str = "system_switches";
+const SYSTEM_FRONTERS: &str = "system_fronters";
+const SWITCH: &str = "switch";
+const MESSAGE: &str = "message";
+const NOT_FOUND: &str = "not_found";
+const ALIAS: &str = "alias";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        kind TEXT NOT NULL,
+        token TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        fetched_at INTEGER NOT NULL,
+        PRIMARY KEY (kind, token, key)
+    );
+";
+
+fn now() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+// Anonymous data lives under an empty token
+fn token_key(token: Option<&str>) -> String {
+    token.map(hash_token).unwrap_or_default()
+}
+
+fn entity_key(entity: Entity, id: &str) -> String {
+    format!("{:?}/{}", entity, id)
+}
+
+// Entries written by a single notification, such as a list along with each of its elements.
+// UUID aliases of the entities it holds are stored for every token rather than the notified one
+#[derive(Default)]
+struct Batch {
+    entries: Vec<(&'static str, String, String)>,
+    aliases: Vec<(String, String)>,
+}
+
+impl Batch {
+    fn add<T: Serialize>(&mut self, kind: &'static str, key: &str, value: &T) {
+        if let Ok(value) = serde_json::to_string(value) {
+            self.entries.push((kind, key.to_string(), value));
+        }
+    }
+
+    fn learn(&mut self, entity: Entity, uuid: &str, id: &str) {
+        if let Ok(id) = serde_json::to_string(id) {
+            self.aliases.push((entity_key(entity, &uuid.to_lowercase()), id));
+        }
+    }
+
+    fn learn_system(&mut self, system: &System) {
+        self.learn(Entity::System, &system.uuid, &system.id);
+    }
+
+    fn learn_members<'a>(&mut self, members: impl IntoIterator<Item = &'a Member>) {
+        for member in members {
+            self.learn(Entity::Member, &member.uuid, &member.id);
+        }
+    }
+
+    fn learn_groups(&mut self, groups: &[Group]) {
+        for group in groups {
+            self.learn(Entity::Group, &group.uuid, &group.id);
+            self.learn_members(group.members.iter().flatten());
+        }
+    }
+
+    fn learn_switch(&mut self, switch: &Switch) {
+        self.learn_members(switch.members.iter().filter_map(|member| match member {
+            MemberOrId::Member(member) => Some(member),
+            MemberOrId::Id(_) => None,
+        }));
+    }
+
+    fn learn_message(&mut self, message: &Message) {
+        self.learn_members(&message.member);
+
+        if let Some(system) = &message.system {
+            self.learn_system(system);
+        }
+    }
+}
+
+// Keeps cached answers on disk so they survive restarts, meant to sit behind the in-memory cache.
+// Every entry is stored as JSON along with when it was fetched
+pub(crate) struct SqliteCache {
+    connection: Mutex<Connection>,
+    ttls: CacheTtls,
+    retention: Duration,
+}
+
+impl SqliteCache {
+    // Expired entries are kept until older than `retention` to be served stale
+    pub fn open(path: impl AsRef<Path>, ttls: CacheTtls, retention: Duration) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        let cache = Self {
+            connection: Mutex::new(connection),
+            ttls,
+            retention,
+        };
+        cache.prune()?;
+
+        Ok(cache)
+    }
+
+    // Called when opening and then periodically, returns how many entries were dropped
+    pub fn prune(&self) -> rusqlite::Result<usize> {
+        self.blocking(|connection| {
+            connection.execute("DELETE FROM entries WHERE fetched_at < ?1", params![now() - self.retention.as_millis() as i64])
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+
+    // SQLite calls block, the runtime is told so it can move other tasks away from this thread
+    fn blocking<T>(&self, call: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+        tokio::task::block_in_place(|| call(&self.connection()))
+    }
+
+    fn load<T: DeserializeOwned>(&self, token: Option<&str>, kind: &str, key: &str, ttl: Duration) -> ProviderResult<T> {
+        let row: rusqlite::Result<Option<(String, i64)>> = self.blocking(|connection| {
+            connection
+                .query_row(
+                    "SELECT value, fetched_at FROM entries WHERE kind = ?1 AND token = ?2 AND key = ?3",
+                    params![kind, token_key(token), key],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+        });
+
+        let (value, fetched_at) = match row {
+            Ok(Some(row)) => row,
+            Ok(None) => return ProviderResult::Miss,
+            Err(error) => return ProviderResult::Failed(Failure::Storage(error.to_string())),
+        };
+
+        let value = match serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&value)) {
+            Ok(value) => value,
+            Err(error) => return ProviderResult::Failed(Failure::Decode { path: error.path().to_string(), message: error.inner().to_string() }),
+        };
+
+        let age = Duration::from_millis(now().saturating_sub(fetched_at).max(0) as u64);
+        if age >= ttl {
+            ProviderResult::Stale(value, age - ttl)
+        } else {
+            ProviderResult::Cached(value, age)
+        }
+    }
+
+    // Write failures only cost a future miss, the answer itself is already on its way.
+    // The whole batch is committed at once rather than one transaction per entry
+    fn commit(&self, token: Option<&str>, batch: Batch) {
+        let token = token_key(token);
+        let shared = token_key(None);
+        let fetched_at = now() - notified_age().as_millis() as i64;
+
+        let _ = self.blocking(|connection| {
+            let transaction = connection.unchecked_transaction()?;
+
+            {
+                let mut statement = transaction.prepare_cached("INSERT OR REPLACE INTO entries (kind, token, key, value, fetched_at) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+
+                for (kind, key, value) in &batch.entries {
+                    statement.execute(params![kind, token, key, value, fetched_at])?;
+                }
+                for (key, id) in &batch.aliases {
+                    statement.execute(params![ALIAS, shared, key, id, fetched_at])?;
+                }
+            }
+
+            transaction.commit()
+        });
+    }
+
+    fn save<T: Serialize>(&self, token: Option<&str>, kind: &'static str, key: &str, value: &T) {
+        let mut batch = Batch::default();
+        batch.add(kind, key, value);
+
+        self.commit(token, batch);
+    }
+
+    // Removes the entries of `id` for every token, along with the ones keyed under it such as guild settings
+    fn forget(&self, kind: &str, id: &str) {
+        let _ = self.blocking(|connection| {
+            connection.execute(
+                "DELETE FROM entries WHERE kind = ?1 AND (key = ?2 OR substr(key, 1, length(?2) + 1) = ?2 || '/')",
+                params![kind, id],
+            )
+        });
+    }
+
+    // Removes every entry embedding an object with the given ID, at any depth of its JSON
+    fn forget_containing(&self, kind: &str, id: &str) {
+        let _ = self.blocking(|connection| {
+            connection.execute(
+                "DELETE FROM entries WHERE kind = ?1 AND EXISTS (SELECT 1 FROM json_tree(entries.value) WHERE json_tree.key = 'id' AND json_tree.atom = ?2)",
+                params![kind, id],
+            )
+        });
+    }
+
+    fn known_missing(&self, token: Option<&str>, entity: Entity, id: &str) -> bool {
+        matches!(self.load::<()>(token, NOT_FOUND, &entity_key(entity, id), self.ttls.not_found), ProviderResult::Cached((), _))
+    }
+
+    fn clear_not_found(&self, entity: Entity, id: &str) {
+        self.forget(NOT_FOUND, &entity_key(entity, id));
+    }
+
+    // Deleted entities are remembered as missing for every token known to the cache
+    fn mark_missing(&self, entity: Entity, id: &str) {
+        let _ = self.blocking(|connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO entries (kind, token, key, value, fetched_at) SELECT DISTINCT ?1, token, ?2, 'null', ?3 FROM entries",
+                params![NOT_FOUND, entity_key(entity, id), now()],
+            )
+        });
+    }
+
+    // @me is only meaningful for the token that resolved it, other aliases are shared
+    fn canonical(&self, token: Option<&str>, entity: Entity, id: &str) -> String {
+        let id = id.to_lowercase();
+        let key = entity_key(entity, &id);
+
+        for token in [token, None] {
+            if let ProviderResult::Cached(alias, _) = self.load::<String>(token, ALIAS, &key, self.ttls.aliases) {
+                return alias;
+            }
+        }
+
+        id
+    }
+
+    // @me is only forgotten for the token it was resolved with, other aliases for everyone
+    fn forget_alias(&self, token: Option<&str>, entity: Entity, alias: &str) {
+        let key = entity_key(entity, alias);
+
+        if alias != "@me" {
+            self.forget(ALIAS, &key);
+            return;
+        }
+
+        let _ = self.blocking(|connection| {
+            connection.execute("DELETE FROM entries WHERE kind = ?1 AND token = ?2 AND key = ?3", params![ALIAS, token_key(token), key])
+        });
+    }
+
+    fn forget_system(&self, id: &str) {
+        for kind in [SYSTEM, SYSTEM_SETTINGS, SYSTEM_GUILD_SETTINGS, SYSTEM_AUTOPROXY] {
+            self.forget(kind, id);
+        }
+
+        self.clear_not_found(Entity::System, id);
+    }
+
+    fn forget_switches(&self, id: &str) {
+        for kind in [SYSTEM_SWITCHES, SYSTEM_FRONTERS, SWITCH] {
+            self.forget(kind, id);
+        }
+    }
+
+    // Lists embedding the entity are dropped rather than edited
+    fn forget_entity(&self, entity: Entity, id: &str) {
+        match entity {
+            Entity::System => {
+                self.forget_system(id);
+                self.forget(SYSTEM_MEMBERS, id);
+                self.forget(SYSTEM_GROUPS, id);
+                self.forget_switches(id);
+                self.forget_containing(MESSAGE, id);
+            }
+            Entity::Member => {
+                for kind in [MEMBER, MEMBER_GROUPS, MEMBER_GUILD_SETTINGS] {
+                    self.forget(kind, id);
+                }
+                for kind in [SYSTEM_MEMBERS, GROUP_MEMBERS, SYSTEM_GROUPS, SYSTEM_FRONTERS, MESSAGE] {
+                    self.forget_containing(kind, id);
+                }
+            }
+            Entity::Group => {
+                for kind in [GROUP, GROUP_MEMBERS] {
+                    self.forget(kind, id);
+                }
+                for kind in [SYSTEM_GROUPS, MEMBER_GROUPS] {
+                    self.forget_containing(kind, id);
+                }
+            }
+            Entity::Message => self.forget(MESSAGE, id),
+        }
+
+        self.clear_not_found(entity, id);
+    }
+}
+
+#[async_trait]
+impl Notifier for SqliteCache {
+    async fn notify_system(&mut self, token: Option<&str>, system: &System) {
+        self.clear_not_found(Entity::System, &system.id);
+        let mut batch = Batch::default();
+        batch.add(SYSTEM, &system.id, system);
+        batch.learn_system(system);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_system_settings(&mut self, token: Option<&str>, system: &str, settings: &SystemSettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        self.save(token, SYSTEM_SETTINGS, system, settings);
+    }
+
+    async fn notify_system_guild_settings(&mut self, token: Option<&str>, system: &str, guild: &str, settings: &SystemGuildSettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        self.save(token, SYSTEM_GUILD_SETTINGS, &format!("{}/{}", system, guild), settings);
+    }
+
+    async fn notify_system_autoproxy(&mut self, token: Option<&str>, system: &str, settings: &AutoproxySettings) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        self.save(token, SYSTEM_AUTOPROXY, system, settings);
+    }
+
+    async fn notify_system_members(&mut self, token: Option<&str>, system: &str, members: &[Member]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        let mut batch = Batch::default();
+        batch.add(SYSTEM_MEMBERS, system, &members);
+        batch.learn_members(members);
+
+        for member in members {
+            batch.add(MEMBER, &member.id, member);
+        }
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_member(&mut self, token: Option<&str>, member: &Member) {
+        self.clear_not_found(Entity::Member, &member.id);
+        let mut batch = Batch::default();
+        batch.add(MEMBER, &member.id, member);
+        batch.learn_members([member]);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_member_groups(&mut self, token: Option<&str>, member: &str, groups: &[Group]) {
+        let member = &self.canonical(token, Entity::Member, member);
+
+        self.clear_not_found(Entity::Member, member);
+        let mut batch = Batch::default();
+        batch.add(MEMBER_GROUPS, member, &groups);
+        batch.learn_groups(groups);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_member_guild_settings(&mut self, token: Option<&str>, member: &str, guild: &str, settings: &MemberGuildSettings) {
+        let member = &self.canonical(token, Entity::Member, member);
+
+        self.clear_not_found(Entity::Member, member);
+        self.save(token, MEMBER_GUILD_SETTINGS, &format!("{}/{}", member, guild), settings);
+    }
+
+    async fn notify_system_groups(&mut self, token: Option<&str>, system: &str, groups: &[Group]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        let mut batch = Batch::default();
+        batch.add(SYSTEM_GROUPS, system, &groups);
+        batch.learn_groups(groups);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_group(&mut self, token: Option<&str>, group: &Group) {
+        self.clear_not_found(Entity::Group, &group.id);
+        let mut batch = Batch::default();
+        batch.add(GROUP, &group.id, group);
+        batch.learn_groups(std::slice::from_ref(group));
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_group_members(&mut self, token: Option<&str>, group: &str, members: &[Member]) {
+        let group = &self.canonical(token, Entity::Group, group);
+
+        self.clear_not_found(Entity::Group, group);
+        let mut batch = Batch::default();
+        batch.add(GROUP_MEMBERS, group, &members);
+        batch.learn_members(members);
+
+        for member in members {
+            batch.add(MEMBER, &member.id, member);
+        }
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_system_switches(&mut self, token: Option<&str>, system: &str, before: &str, limit: u64, switches: &[Switch]) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        let mut batch = Batch::default();
+        batch.add(SYSTEM_SWITCHES, &format!("{}/{}/{}", system, before, limit), &switches);
+
+        for switch in switches {
+            batch.add(SWITCH, &format!("{}/{}", system, switch.id), switch);
+            batch.learn_switch(switch);
+        }
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_system_fronters(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        let mut batch = Batch::default();
+        batch.add(SYSTEM_FRONTERS, system, switch);
+        batch.learn_switch(switch);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_switch(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        let system = &self.canonical(token, Entity::System, system);
+
+        self.clear_not_found(Entity::System, system);
+        let mut batch = Batch::default();
+        batch.add(SWITCH, &format!("{}/{}", system, switch.id), switch);
+        batch.learn_switch(switch);
+
+        self.commit(token, batch);
+    }
+
+    async fn notify_message(&mut self, token: Option<&str>, message: &Message) {
+        self.clear_not_found(Entity::Message, &message.id);
+        let mut batch = Batch::default();
+        batch.add(MESSAGE, &message.id, message);
+        batch.learn_message(message);
+
+        self.commit(token, batch);
+    }
+
+    // Kept under the ID as requested, a 404 for an alias may only mean the alias is gone
+    async fn notify_not_found(&mut self, token: Option<&str>, entity: Entity, id: &str) {
+        let id = id.to_lowercase();
+
+        self.forget_alias(token, entity, &id);
+        self.save(token, NOT_FOUND, &entity_key(entity, &id), &());
+    }
+
+    async fn notify_alias(&mut self, token: Option<&str>, entity: Entity, alias: &str, id: &str) {
+        let alias = alias.to_lowercase();
+        let token = if alias == "@me" { token } else { None };
+
+        self.save(token, ALIAS, &entity_key(entity, &alias), &id);
+    }
+
+    async fn notify_deleted(&mut self, entity: Entity, id: &str) {
+        let id = &self.canonical(None, entity, id);
+
+        self.forget_entity(entity, id);
+        self.mark_missing(entity, id);
+    }
+
+    // Stored pages can't be edited in place, so every switch of the system is dropped
+    async fn notify_switch_deleted(&mut self, system: &str, _switch: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.forget_switches(system);
+    }
+
+    async fn notify_invalidated(&mut self, entity: Entity, id: &str) {
+        let id = &self.canonical(None, entity, id);
+
+        match entity {
+            Entity::System => self.forget_system(id),
+            entity => self.forget_entity(entity, id),
+        }
+    }
+
+    async fn notify_alias_invalidated(&mut self, entity: Entity, alias: &str) {
+        self.forget_alias(None, entity, alias);
+    }
+
+    async fn notify_system_members_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.forget(SYSTEM_MEMBERS, system);
+    }
+
+    async fn notify_system_groups_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.forget(SYSTEM_GROUPS, system);
+    }
+
+    async fn notify_system_switches_invalidated(&mut self, system: &str) {
+        let system = &self.canonical(None, Entity::System, system);
+
+        self.forget_switches(system);
+    }
+
+    async fn notify_member_groups_invalidated(&mut self, member: &str) {
+        let member = &self.canonical(None, Entity::Member, member);
+
+        self.forget(MEMBER_GROUPS, member);
+    }
+
+    // UUIDs are never @me, their aliases are shared. Expired ones are still good, an entity keeps its UUID
+    async fn resolve_uuid(&mut self, entity: Entity, uuid: &str) -> Option<String> {
+        let uuid = uuid.to_lowercase();
+
+        match self.load::<String>(None, ALIAS, &entity_key(entity, &uuid), Duration::MAX) {
+            ProviderResult::Cached(id, _) | ProviderResult::Stale(id, _) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for SqliteCache {
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, SYSTEM, id, self.ttls.systems)
+    }
+
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, SYSTEM_SETTINGS, id, self.ttls.settings)
+    }
+
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        self.load(token, SYSTEM_GUILD_SETTINGS, &format!("{}/{}", id, guild), self.ttls.settings)
+    }
+
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, SYSTEM_AUTOPROXY, id, self.ttls.autoproxy)
+    }
+
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, SYSTEM_MEMBERS, id, self.ttls.members)
+    }
+
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        if self.known_missing(token, Entity::Member, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, MEMBER, id, self.ttls.members)
+    }
+
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        if self.known_missing(token, Entity::Member, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, MEMBER_GROUPS, id, self.ttls.groups)
+    }
+
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        let id = &self.canonical(token, Entity::Member, id);
+
+        self.load(token, MEMBER_GUILD_SETTINGS, &format!("{}/{}", id, guild), self.ttls.settings)
+    }
+
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        let mut result = self.load::<Vec<Group>>(token, SYSTEM_GROUPS, id, self.ttls.groups);
+        let groups = match &mut result {
+            ProviderResult::Cached(groups, _) | ProviderResult::Stale(groups, _) => groups,
+            _ => return result,
+        };
+
+        if with_member {
+            if !groups.iter().all(|group| group.members.is_some()) {
+                return ProviderResult::Miss;
+            }
+        } else {
+            for group in groups.iter_mut() {
+                group.members = None;
+            }
+        }
+
+        result
+    }
+
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        let id = &self.canonical(token, Entity::Group, id);
+
+        if self.known_missing(token, Entity::Group, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, GROUP, id, self.ttls.groups)
+    }
+
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        let id = &self.canonical(token, Entity::Group, id);
+
+        if self.known_missing(token, Entity::Group, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, GROUP_MEMBERS, id, self.ttls.members)
+    }
+
+    // Pages are stored as they were requested, the latest one can change with every new switch
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        let ttl = if before.is_empty() { self.ttls.front } else { self.ttls.switches };
+        self.load(token, SYSTEM_SWITCHES, &format!("{}/{}/{}", id, before, limit), ttl)
+    }
+
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        if self.known_missing(token, Entity::System, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, SYSTEM_FRONTERS, id, self.ttls.front)
+    }
+
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        let id = &self.canonical(token, Entity::System, id);
+
+        self.load(token, SWITCH, &format!("{}/{}", id, switch_id), self.ttls.switches)
+    }
+
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        if self.known_missing(token, Entity::Message, id) {
+            return ProviderResult::NotFound;
+        }
+
+        self.load(token, MESSAGE, id, self.ttls.messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::traits::notifier::fetched_ago;
+    use super::*;
+
+    const TOKEN: Option<&str> = Some("owner token");
+
+    fn cache() -> SqliteCache {
+        SqliteCache::open(":memory:", CacheTtls::default(), Duration::from_secs(60)).unwrap()
+    }
+
+    fn system(id: &str) -> System {
+        serde_json::from_value(json!({ "id": id, "uuid": format!("00000000-0000-0000-0000-0000000{id}") })).unwrap()
+    }
+
+    fn member(id: &str) -> Member {
+        serde_json::from_value(json!({ "id": id, "uuid": format!("00000000-0000-0000-0000-0000000{id}"), "name": id, "proxy_tags": [], "keep_proxy": false })).unwrap()
+    }
+
+    fn fresh<T>(result: ProviderResult<T>) -> Option<T> {
+        match result {
+            ProviderResult::Cached(value, _) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn entries_are_read_back_for_their_token() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system("sysid")).await;
+
+        assert_eq!(fresh(cache.get_system(TOKEN, "sysid").await), Some(system("sysid")));
+        assert_eq!(fresh(cache.get_system(TOKEN, "SYSID").await), Some(system("sysid")));
+        assert_eq!(cache.get_system(None, "sysid").await, ProviderResult::Miss);
+        assert_eq!(cache.get_system(Some("other token"), "sysid").await, ProviderResult::Miss);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lists_store_each_of_their_elements() {
+        let mut cache = cache();
+        cache.notify_system_members(TOKEN, "sysid", &[member("aaaaa"), member("bbbbb")]).await;
+
+        assert_eq!(fresh(cache.get_system_members(TOKEN, "sysid").await), Some(vec![member("aaaaa"), member("bbbbb")]));
+        assert_eq!(fresh(cache.get_member(TOKEN, "bbbbb").await), Some(member("bbbbb")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn entries_past_their_ttl_are_stale() {
+        let mut cache = SqliteCache::open(":memory:", CacheTtls { systems: Duration::from_secs(60), ..CacheTtls::default() }, Duration::from_secs(3600)).unwrap();
+        fetched_ago(Duration::from_secs(90), cache.notify_system(TOKEN, &system("sysid"))).await;
+
+        match cache.get_system(TOKEN, "sysid").await {
+            ProviderResult::Stale(value, expired_for) => {
+                assert_eq!(value, system("sysid"));
+                assert!(expired_for >= Duration::from_secs(30) && expired_for < Duration::from_secs(31), "{expired_for:?}");
+            }
+            result => panic!("expected stale data, got {result:?}"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn entries_past_the_retention_are_pruned() {
+        let mut cache = cache();
+        fetched_ago(Duration::from_secs(120), cache.notify_system(TOKEN, &system("oldid"))).await;
+        cache.notify_system(TOKEN, &system("newid")).await;
+
+        cache.prune().unwrap();
+
+        assert_eq!(cache.get_system(TOKEN, "oldid").await, ProviderResult::Miss);
+        assert!(fresh(cache.get_system(TOKEN, "newid").await).is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn not_found_is_remembered_until_the_entity_is_seen() {
+        let mut cache = cache();
+        cache.notify_not_found(TOKEN, Entity::Member, "abcde").await;
+
+        assert_eq!(cache.get_member(TOKEN, "abcde").await, ProviderResult::NotFound);
+        assert_eq!(cache.get_member_groups(TOKEN, "ABCDE").await, ProviderResult::NotFound);
+        assert_eq!(cache.get_member(None, "abcde").await, ProviderResult::Miss);
+
+        cache.notify_member(None, &member("abcde")).await;
+
+        assert_eq!(cache.get_member(TOKEN, "abcde").await, ProviderResult::Miss);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deleted_entities_are_missing_for_every_token() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system("sysid")).await;
+        cache.notify_member(TOKEN, &member("aaaaa")).await;
+        cache.notify_member(None, &member("aaaaa")).await;
+
+        cache.notify_deleted(Entity::System, "sysid").await;
+
+        assert_eq!(cache.get_system(TOKEN, "sysid").await, ProviderResult::NotFound);
+        assert_eq!(cache.get_system(None, "sysid").await, ProviderResult::NotFound);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deleted_members_are_dropped_from_the_lists_embedding_them() {
+        let mut cache = cache();
+        cache.notify_system_members(TOKEN, "sysid", &[member("aaaaa"), member("bbbbb")]).await;
+        cache.notify_group_members(TOKEN, "grpid", &[member("bbbbb")]).await;
+
+        cache.notify_deleted(Entity::Member, "aaaaa").await;
+
+        assert_eq!(cache.get_system_members(TOKEN, "sysid").await, ProviderResult::Miss);
+        assert_eq!(fresh(cache.get_group_members(TOKEN, "grpid").await), Some(vec![member("bbbbb")]));
+        assert_eq!(fresh(cache.get_member(TOKEN, "bbbbb").await), Some(member("bbbbb")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalidation_forgets_entries_keyed_under_the_id_only() {
+        let mut cache = cache();
+        let settings: SystemGuildSettings = serde_json::from_value(json!({ "guild_id": "123", "proxying_enabled": true, "tag": null, "tag_enabled": true })).unwrap();
+        cache.notify_system(TOKEN, &system("abcde")).await;
+        cache.notify_system(TOKEN, &system("abcdef")).await;
+        cache.notify_system_guild_settings(TOKEN, "abcde", "123", &settings).await;
+        cache.notify_system_guild_settings(TOKEN, "abcdef", "123", &settings).await;
+
+        cache.notify_invalidated(Entity::System, "abcde").await;
+
+        assert_eq!(cache.get_system(TOKEN, "abcde").await, ProviderResult::Miss);
+        assert_eq!(cache.get_system_guild_settings(TOKEN, "abcde", "123").await, ProviderResult::Miss);
+        assert!(fresh(cache.get_system(TOKEN, "abcdef").await).is_some());
+        assert!(fresh(cache.get_system_guild_settings(TOKEN, "abcdef", "123").await).is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn aliases_are_shared_except_for_me() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system("sysid")).await;
+        cache.notify_alias(TOKEN, Entity::System, "123456789", "sysid").await;
+        cache.notify_alias(TOKEN, Entity::System, "@me", "sysid").await;
+
+        assert_eq!(fresh(cache.get_system(TOKEN, "123456789").await), Some(system("sysid")));
+        assert_eq!(fresh(cache.get_system(TOKEN, "@me").await), Some(system("sysid")));
+        assert_eq!(cache.canonical(Some("other token"), Entity::System, "123456789"), "sysid");
+        assert_eq!(cache.canonical(Some("other token"), Entity::System, "@me"), "@me");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alias_not_found_leaves_the_entity_alone() {
+        let mut cache = cache();
+        cache.notify_system(TOKEN, &system("sysid")).await;
+        cache.notify_alias(TOKEN, Entity::System, "123456789", "sysid").await;
+
+        cache.notify_not_found(TOKEN, Entity::System, "123456789").await;
+
+        assert_eq!(cache.get_system(TOKEN, "123456789").await, ProviderResult::NotFound);
+        assert_eq!(fresh(cache.get_system(TOKEN, "sysid").await), Some(system("sysid")));
+        assert_eq!(cache.canonical(TOKEN, Entity::System, "123456789"), "123456789");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn uuids_of_embedded_entities_are_resolved() {
+        let mut cache = cache();
+        let group: Group = serde_json::from_value(json!({ "id": "grpid", "uuid": "00000000-0000-0000-0000-000000000002", "name": "Group", "members": [member("aaaaa")] })).unwrap();
+        cache.notify_member_groups(TOKEN, "aaaaa", &[group]).await;
+
+        assert_eq!(cache.resolve_uuid(Entity::Group, "00000000-0000-0000-0000-000000000002").await, Some("grpid".to_string()));
+        assert_eq!(cache.resolve_uuid(Entity::Member, "00000000-0000-0000-0000-0000000AAAAA").await, Some("aaaaa".to_string()));
+        assert_eq!(cache.resolve_uuid(Entity::Member, "00000000-0000-0000-0000-000000000002").await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn system_groups_without_members_are_not_served_with_them() {
+        let mut cache = cache();
+        let group: Group = serde_json::from_value(json!({ "id": "grpid", "uuid": "00000000-0000-0000-0000-000000000002", "name": "Group", "members": [member("aaaaa")] })).unwrap();
+        cache.notify_system_groups(TOKEN, "sysid", &[Group { members: None, ..group.clone() }]).await;
+
+        assert_eq!(cache.get_system_groups(TOKEN, "sysid", true).await, ProviderResult::Miss);
+
+        cache.notify_system_groups(TOKEN, "sysid", std::slice::from_ref(&group)).await;
+
+        assert_eq!(fresh(cache.get_system_groups(TOKEN, "sysid", true).await), Some(vec![group.clone()]));
+        assert_eq!(fresh(cache.get_system_groups(TOKEN, "sysid", false).await), Some(vec![Group { members: None, ..group }]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deleted_switches_drop_every_page_of_the_system() {
+        let mut cache = cache();
+        let switch: Switch = serde_json::from_value(json!({ "id": "switch", "timestamp": "2024-01-01T00:00:00Z", "members": ["aaaaa"] })).unwrap();
+        cache.notify_system_switches(TOKEN, "sysid", "", 100, std::slice::from_ref(&switch)).await;
+        cache.notify_system_switches(TOKEN, "othid", "", 100, std::slice::from_ref(&switch)).await;
+
+        assert_eq!(fresh(cache.get_switch(TOKEN, "sysid", "switch").await), Some(switch.clone()));
+
+        cache.notify_switch_deleted("sysid", "switch").await;
+
+        assert_eq!(cache.get_system_switches(TOKEN, "sysid", "", 100).await, ProviderResult::Miss);
+        assert_eq!(cache.get_switch(TOKEN, "sysid", "switch").await, ProviderResult::Miss);
+        assert_eq!(fresh(cache.get_system_switches(TOKEN, "othid", "", 100).await), Some(vec![switch]));
+    }
+}
@@ -1,19 +1,132 @@
 mod models;
+mod errors;
+mod privacy;
 mod traits;
 mod implementations;
+mod server;
+mod single_flight;
+mod dispatch;
+mod config;
 
+use std::path::Path;
 use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
 use implementations::in_memory_cache::InMemoryCache;
 use implementations::origin_api::OriginApi;
+use implementations::sqlite_cache::SqliteCache;
 use implementations::controller::Controller;
+use dispatch::Dispatcher;
+use config::{Config, Layer};
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+// Only copying the cache holds its lock, serializing and writing the copy doesn't
+async fn save_snapshot(cache: &Mutex<&mut InMemoryCache>, path: &Path) {
+    let snapshot = cache.lock().await.snapshot();
+
+    if let Err(error) = tokio::task::block_in_place(|| snapshot.save(path)) {
+        eprintln!("Failed to save the cache snapshot to {}: {}", path.display(), error);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Invalid configuration: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut origin_api = OriginApi::new(&config.origin.url, config.origin.timeout, config.origin.connect_timeout);
+    origin_api.set_retry_policy(config.origin.retry.clone());
+    origin_api.set_rate_limit_policy(config.origin.rate_limit.clone());
+    origin_api.set_circuit_breaker_policy(config.origin.circuit_breaker.clone());
+    let circuit_breaker = origin_api.circuit_breaker();
+    let origin_api = Arc::new(origin_api);
 
-fn main() {
-    let memory_cache: Arc<Mutex<&mut InMemoryCache>> = Arc::new(Mutex::new(Box::leak(Box::new(InMemoryCache::new()))));
-    let origin_api: Arc<Mutex<&mut OriginApi>> = Arc::new(Mutex::new(Box::leak(Box::new(OriginApi::new("https://api.pluralkit.me/v2".to_string())))));
     let mut controller = Controller::new();
+    controller.set_stale_policy(config.stale.clone());
+
+    // Layers are asked in the configured order, the origin always comes last
+    let mut memory_cache = None;
+    for layer in config.layers() {
+        match layer {
+            Layer::Memory => {
+                let cache: Arc<Mutex<&mut InMemoryCache>> = Arc::new(Mutex::new(Box::leak(Box::new(InMemoryCache::new(config.ttls.clone(), config.memory.clone())))));
+
+                if let Some(path) = &config.snapshot.path {
+                    if let Err(error) = cache.lock().await.load_snapshot(path) {
+                        eprintln!("Discarding the cache snapshot at {}: {}", path.display(), error);
+                    }
+
+                    if let Some(interval) = config.snapshot.interval {
+                        let (cache, path) = (cache.clone(), path.clone());
+
+                        tokio::spawn(async move {
+                            let mut interval = tokio::time::interval(interval);
+                            interval.tick().await;
+
+                            loop {
+                                interval.tick().await;
+                                save_snapshot(&cache, &path).await;
+                            }
+                        });
+                    }
+                }
+
+                controller.add_notifier(cache.clone());
+                controller.add_provider(cache.clone());
+                memory_cache = Some(cache);
+            }
+            Layer::Sqlite => {
+                // Validation guarantees the path is set
+                let path = config.sqlite.path.as_ref().unwrap();
+                let sqlite_cache = match SqliteCache::open(path, config.ttls.clone(), config.sqlite.retention) {
+                    Ok(sqlite_cache) => sqlite_cache,
+                    Err(error) => {
+                        eprintln!("Failed to open the SQLite cache at {}: {}", path.display(), error);
+                        std::process::exit(1);
+                    }
+                };
+                let sqlite_cache: Arc<Mutex<&mut SqliteCache>> = Arc::new(Mutex::new(Box::leak(Box::new(sqlite_cache))));
+
+                let (cache, prune_interval) = (sqlite_cache.clone(), config.sqlite.prune_interval);
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(prune_interval);
+                    interval.tick().await;
+
+                    loop {
+                        interval.tick().await;
+
+                        if let Err(error) = cache.lock().await.prune() {
+                            eprintln!("Failed to prune the SQLite cache: {}", error);
+                        }
+                    }
+                });
+
+                controller.add_notifier(sqlite_cache.clone());
+                controller.add_provider(sqlite_cache.clone());
+            }
+        }
+    }
+
+    controller.add_provider(origin_api);
+
+    let dispatcher = config.signing_token.clone().map(|token| Dispatcher::new(controller.notifiers(), token));
+
+    server::serve(controller, dispatcher, circuit_breaker, config.listen, shutdown_signal()).await;
 
-    controller.add_notifier(memory_cache.clone());
-    controller.add_provider(memory_cache.clone());
-    controller.add_provider(origin_api.clone());
+    if let (Some(cache), Some(path)) = (&memory_cache, &config.snapshot.path) {
+        save_snapshot(cache, path).await;
+    }
 }
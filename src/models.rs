@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use serde;
 
 /* Models */
 
@@ -146,6 +145,7 @@ pub struct MemberGuildSettings {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum AutoproxyMode {
     OFF,
     FRONT,
@@ -155,6 +155,7 @@ pub enum AutoproxyMode {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum PrivacyKey {
     PRIVATE,
     PUBLIC,
@@ -162,6 +163,7 @@ pub enum PrivacyKey {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum MemberOrId {
     Member(Member),
     Id(String),
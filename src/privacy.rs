@@ -0,0 +1,266 @@
+use crate::models::{Group, Member, PrivacyKey, System};
+
+fn is_private(key: &Option<PrivacyKey>) -> bool {
+    matches!(key, Some(PrivacyKey::PRIVATE))
+}
+
+// PluralKit only sends privacy settings to the owner, so their presence marks a full view
+pub trait PrivacyView: Sized {
+    fn is_owner_view(&self) -> bool;
+    fn public_view(&self) -> Option<Self>;
+}
+
+impl System {
+    pub fn member_list_private(&self) -> bool {
+        self.privacy.as_ref().is_some_and(|privacy| is_private(&privacy.member_list_privacy))
+    }
+
+    pub fn group_list_private(&self) -> bool {
+        self.privacy.as_ref().is_some_and(|privacy| is_private(&privacy.group_list_privacy))
+    }
+}
+
+impl PrivacyView for System {
+    fn is_owner_view(&self) -> bool {
+        self.privacy.is_some()
+    }
+
+    fn public_view(&self) -> Option<Self> {
+        let mut system = self.clone();
+
+        if let Some(privacy) = system.privacy.take() {
+            if is_private(&privacy.description_privacy) {
+                system.description = None;
+            }
+            if is_private(&privacy.pronoun_privacy) {
+                system.pronouns = None;
+            }
+        }
+
+        Some(system)
+    }
+}
+
+impl Member {
+    pub fn is_hidden(&self) -> bool {
+        self.privacy.as_ref().is_some_and(|privacy| is_private(&privacy.visibility))
+    }
+}
+
+impl PrivacyView for Member {
+    fn is_owner_view(&self) -> bool {
+        self.privacy.is_some()
+    }
+
+    fn public_view(&self) -> Option<Self> {
+        if self.is_hidden() {
+            return None;
+        }
+
+        let mut member = self.clone();
+
+        if let Some(privacy) = member.privacy.take() {
+            if is_private(&privacy.name_privacy) {
+                member.name = member.display_name.take().unwrap_or(member.name);
+            }
+            if is_private(&privacy.description_privacy) {
+                member.description = None;
+                member.banner = None;
+            }
+            if is_private(&privacy.birthday_privacy) {
+                member.birthday = None;
+            }
+            if is_private(&privacy.pronoun_privacy) {
+                member.pronouns = None;
+            }
+            if is_private(&privacy.avatar_privacy) {
+                member.avatar_url = None;
+            }
+            if is_private(&privacy.metadata_privacy) {
+                member.created = None;
+            }
+        }
+
+        Some(member)
+    }
+}
+
+impl Group {
+    pub fn is_hidden(&self) -> bool {
+        self.privacy.as_ref().is_some_and(|privacy| is_private(&privacy.visibility))
+    }
+
+    pub fn member_list_private(&self) -> bool {
+        self.privacy.as_ref().is_some_and(|privacy| is_private(&privacy.list_privacy))
+    }
+}
+
+impl PrivacyView for Group {
+    fn is_owner_view(&self) -> bool {
+        self.privacy.is_some()
+    }
+
+    fn public_view(&self) -> Option<Self> {
+        if self.is_hidden() {
+            return None;
+        }
+
+        let list_private = self.member_list_private();
+        let mut group = self.clone();
+
+        if let Some(privacy) = group.privacy.take() {
+            if is_private(&privacy.name_privacy) {
+                group.name = group.display_name.take().unwrap_or(group.name);
+            }
+            if is_private(&privacy.description_privacy) {
+                group.description = None;
+                group.banner = None;
+            }
+            if is_private(&privacy.icon_privacy) {
+                group.icon = None;
+            }
+        }
+
+        group.members = match group.members {
+            Some(_) if list_private => None,
+            Some(members) => Some(members.iter().filter_map(Member::public_view).collect()),
+            None => None,
+        };
+
+        Some(group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::*;
+
+    fn member(privacy: Value) -> Member {
+        serde_json::from_value(json!({
+            "id": "abcde",
+            "uuid": "00000000-0000-0000-0000-000000000001",
+            "name": "Private name",
+            "display_name": "Shown name",
+            "birthday": "2000-01-01",
+            "pronouns": "they/them",
+            "avatar_url": "https://example.com/avatar.png",
+            "banner": "https://example.com/banner.png",
+            "description": "Description",
+            "created": "2020-01-01T00:00:00Z",
+            "proxy_tags": [],
+            "keep_proxy": false,
+            "privacy": privacy,
+        })).unwrap()
+    }
+
+    fn group(privacy: Value, members: Vec<Member>) -> Group {
+        serde_json::from_value(json!({
+            "id": "fghij",
+            "uuid": "00000000-0000-0000-0000-000000000002",
+            "name": "Private name",
+            "display_name": "Shown name",
+            "description": "Description",
+            "icon": "https://example.com/icon.png",
+            "banner": "https://example.com/banner.png",
+            "privacy": privacy,
+            "members": members,
+        })).unwrap()
+    }
+
+    #[test]
+    fn member_without_privacy_settings_is_unchanged() {
+        let member = member(Value::Null);
+
+        assert!(!member.is_owner_view());
+        assert_eq!(member.public_view(), Some(member));
+    }
+
+    #[test]
+    fn hidden_members_have_no_public_view() {
+        assert_eq!(member(json!({ "visibility": "private" })).public_view(), None);
+    }
+
+    #[test]
+    fn member_public_view_strips_private_fields() {
+        let member = member(json!({
+            "visibility": "public",
+            "name_privacy": "private",
+            "description_privacy": "private",
+            "birthday_privacy": "private",
+            "pronoun_privacy": "private",
+            "avatar_privacy": "private",
+            "metadata_privacy": "private",
+        }));
+        assert!(member.is_owner_view());
+
+        let public = member.public_view().unwrap();
+
+        assert_eq!(public.name, "Shown name");
+        assert_eq!(public.display_name, None);
+        assert_eq!(public.description, None);
+        assert_eq!(public.banner, None);
+        assert_eq!(public.birthday, None);
+        assert_eq!(public.pronouns, None);
+        assert_eq!(public.avatar_url, None);
+        assert_eq!(public.created, None);
+        assert_eq!(public.privacy, None);
+        assert!(!public.is_owner_view());
+    }
+
+    #[test]
+    fn member_public_view_keeps_public_fields() {
+        let member = member(json!({ "visibility": "public", "name_privacy": "public", "pronoun_privacy": "public" }));
+        let public = member.public_view().unwrap();
+
+        assert_eq!(public, Member { privacy: None, ..member });
+    }
+
+    #[test]
+    fn system_public_view_strips_private_fields() {
+        let system: System = serde_json::from_value(json!({
+            "id": "klmno",
+            "uuid": "00000000-0000-0000-0000-000000000003",
+            "name": "System",
+            "description": "Description",
+            "pronouns": "they/them",
+            "privacy": { "description_privacy": "private", "pronoun_privacy": "public", "member_list_privacy": "private" },
+        })).unwrap();
+        assert!(system.member_list_private());
+        assert!(!system.group_list_private());
+
+        let public = system.public_view().unwrap();
+
+        assert_eq!(public.description, None);
+        assert_eq!(public.pronouns.as_deref(), Some("they/them"));
+        assert_eq!(public.privacy, None);
+    }
+
+    #[test]
+    fn hidden_groups_have_no_public_view() {
+        assert_eq!(group(json!({ "visibility": "private" }), Vec::new()).public_view(), None);
+    }
+
+    #[test]
+    fn group_public_view_filters_hidden_members() {
+        let shown = member(json!({ "visibility": "public" }));
+        let hidden = member(json!({ "visibility": "private" }));
+        let group = group(json!({ "visibility": "public", "list_privacy": "public" }), vec![shown.clone(), hidden]);
+
+        let public = group.public_view().unwrap();
+
+        assert_eq!(public.members, Some(vec![shown.public_view().unwrap()]));
+    }
+
+    #[test]
+    fn group_public_view_drops_private_member_lists() {
+        let group = group(json!({ "list_privacy": "private", "icon_privacy": "private", "name_privacy": "private" }), vec![member(Value::Null)]);
+        assert!(group.member_list_private());
+
+        let public = group.public_view().unwrap();
+
+        assert_eq!(public.members, None);
+        assert_eq!(public.icon, None);
+        assert_eq!(public.name, "Shown name");
+    }
+}
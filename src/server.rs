@@ -0,0 +1,284 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use serde::{Deserialize, Serialize};
+use warp::http::header::{HeaderValue, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+use crate::dispatch::{DispatchEvent, Dispatcher};
+use crate::errors::{error_for, Resource};
+use crate::implementations::controller::Controller;
+use crate::implementations::in_memory_cache::MAX_PAGE_SIZE;
+use crate::implementations::origin_api::{CircuitBreaker, CircuitStatus};
+use crate::traits::provider::{Provider, ProviderResult};
+
+#[derive(Deserialize)]
+struct SystemGroupsQuery {
+    with_members: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct SwitchesQuery {
+    before: Option<String>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    origin: CircuitStatus,
+}
+
+fn with_controller(controller: Controller) -> impl Filter<Extract = (Controller,), Error = Infallible> + Clone {
+    warp::any().map(move || controller.clone())
+}
+
+fn with_dispatcher(dispatcher: Option<Dispatcher>) -> impl Filter<Extract = (Option<Dispatcher>,), Error = Infallible> + Clone {
+    warp::any().map(move || dispatcher.clone())
+}
+
+fn with_circuit_breaker(circuit_breaker: CircuitBreaker) -> impl Filter<Extract = (CircuitBreaker,), Error = Infallible> + Clone {
+    warp::any().map(move || circuit_breaker.clone())
+}
+
+fn with_token() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+}
+
+fn into_response<T: Serialize>(resource: Resource, result: ProviderResult<T>) -> Response {
+    match result {
+        ProviderResult::Ok(value) | ProviderResult::Cached(value, _) => return warp::reply::json(&value).into_response(),
+        ProviderResult::Stale(value, _) => {
+            let reply = warp::reply::json(&value);
+            return warp::reply::with_header(reply, "Warning", "110 pluralcache \"Response is Stale\"").into_response();
+        }
+        _ => {}
+    }
+
+    let retry_after = match result {
+        ProviderResult::RateLimited(retry_after) => Some(retry_after),
+        _ => None,
+    };
+
+    match error_for(resource, &result) {
+        Some((status, error)) => {
+            let mut response = warp::reply::with_status(warp::reply::json(&error), status).into_response();
+
+            // Retry-After only has a precision of a second, rounded up so clients don't come back too early
+            if let Some(retry_after) = retry_after {
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+            }
+
+            response
+        }
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub(crate) fn routes(controller: Controller, dispatcher: Option<Dispatcher>, circuit_breaker: CircuitBreaker) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let system = warp::path!("systems" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::System, controller.get_system(token.as_deref(), &id).await)
+        });
+
+    let system_settings = warp::path!("systems" / String / "settings")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemSettings, controller.get_system_settings(token.as_deref(), &id).await)
+        });
+
+    let system_guild_settings = warp::path!("systems" / String / "guilds" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, guild: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemGuildSettings, controller.get_system_guild_settings(token.as_deref(), &id, &guild).await)
+        });
+
+    let system_autoproxy = warp::path!("systems" / String / "autoproxy")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemAutoproxy, controller.get_system_autoproxy(token.as_deref(), &id).await)
+        });
+
+    let system_members = warp::path!("systems" / String / "members")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemMembers, controller.get_system_members(token.as_deref(), &id).await)
+        });
+
+    let member = warp::path!("members" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::Member, controller.get_member(token.as_deref(), &id).await)
+        });
+
+    let member_groups = warp::path!("members" / String / "groups")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::MemberGroups, controller.get_member_groups(token.as_deref(), &id).await)
+        });
+
+    let member_guild_settings = warp::path!("members" / String / "guilds" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, guild: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::MemberGuildSettings, controller.get_member_guild_settings(token.as_deref(), &id, &guild).await)
+        });
+
+    let system_groups = warp::path!("systems" / String / "groups")
+        .and(warp::query::<SystemGroupsQuery>())
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, query: SystemGroupsQuery, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemGroups, controller.get_system_groups(token.as_deref(), &id, query.with_members.unwrap_or(false)).await)
+        });
+
+    let group = warp::path!("groups" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::Group, controller.get_group(token.as_deref(), &id).await)
+        });
+
+    let group_members = warp::path!("groups" / String / "members")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::GroupMembers, controller.get_group_members(token.as_deref(), &id).await)
+        });
+
+    let system_switches = warp::path!("systems" / String / "switches")
+        .and(warp::query::<SwitchesQuery>())
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, query: SwitchesQuery, token: Option<String>, controller: Controller| async move {
+            let before = query.before.unwrap_or_default();
+            let limit = query.limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+            into_response(Resource::SystemSwitches, controller.get_system_switches(token.as_deref(), &id, &before, limit).await)
+        });
+
+    let switch = warp::path!("systems" / String / "switches" / String)
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, switch_id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::Switch, controller.get_switch(token.as_deref(), &id, &switch_id).await)
+        });
+
+    let system_fronters = warp::path!("systems" / String / "fronters")
+        .and(with_token())
+        .and(with_controller(controller.clone()))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::SystemFronters, controller.get_system_fronters(token.as_deref(), &id).await)
+        });
+
+    let message = warp::path!("messages" / String)
+        .and(with_token())
+        .and(with_controller(controller))
+        .then(|id: String, token: Option<String>, controller: Controller| async move {
+            into_response(Resource::Message, controller.get_message(token.as_deref(), &id).await)
+        });
+
+    // Dispatch events are only accepted once a signing token is configured
+    let dispatch = warp::post()
+        .and(warp::path!("dispatch"))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_dispatcher(dispatcher))
+        .then(|event: DispatchEvent, dispatcher: Option<Dispatcher>| async move {
+            match dispatcher {
+                Some(dispatcher) => dispatcher.handle(event).await.into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        });
+
+    let status = warp::get()
+        .and(warp::path!("status"))
+        .and(with_circuit_breaker(circuit_breaker))
+        .map(|circuit_breaker: CircuitBreaker| warp::reply::json(&Status { origin: circuit_breaker.status() }).into_response());
+
+    let api = warp::get().and(warp::path("v2")).and(
+        system
+            .or(system_settings).unify()
+            .or(system_guild_settings).unify()
+            .or(system_autoproxy).unify()
+            .or(system_members).unify()
+            .or(member).unify()
+            .or(member_groups).unify()
+            .or(member_guild_settings).unify()
+            .or(system_groups).unify()
+            .or(group).unify()
+            .or(group_members).unify()
+            .or(system_switches).unify()
+            .or(switch).unify()
+            .or(system_fronters).unify()
+            .or(message).unify()
+    );
+
+    api.or(dispatch).unify().or(status).unify()
+}
+
+// Returns once `shutdown` completes and every in-flight request has been answered
+pub(crate) async fn serve(controller: Controller, dispatcher: Option<Dispatcher>, circuit_breaker: CircuitBreaker, address: SocketAddr, shutdown: impl Future<Output = ()> + Send + 'static) {
+    let (_, server) = warp::serve(routes(controller, dispatcher, circuit_breaker)).bind_with_graceful_shutdown(address, shutdown);
+    server.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::{json, Value};
+    use tokio::sync::Mutex;
+    use crate::implementations::in_memory_cache::{CacheLimits, CacheTtls, InMemoryCache};
+    use crate::implementations::origin_api::CircuitBreakerPolicy;
+    use crate::models::Switch;
+    use crate::traits::notifier::Notifier;
+    use super::*;
+
+    fn switch(id: &str, timestamp: &str) -> Switch {
+        serde_json::from_value(json!({ "id": id, "timestamp": timestamp, "members": [] })).unwrap()
+    }
+
+    async fn controller() -> Controller {
+        let mut cache = InMemoryCache::new(CacheTtls::default(), CacheLimits::default());
+        let switches = [switch("newest", "2024-01-02T00:00:00Z"), switch("oldest", "2024-01-01T00:00:00Z")];
+        cache.notify_system_switches(None, "sysid", "", MAX_PAGE_SIZE, &switches).await;
+
+        let cache = Arc::new(Mutex::new(cache));
+        let mut controller = Controller::new();
+        controller.add_provider(cache.clone());
+        controller.add_notifier(cache);
+
+        controller
+    }
+
+    async fn switches(path: &str) -> Vec<Value> {
+        let routes = routes(controller().await, None, CircuitBreaker::new(CircuitBreakerPolicy::default()));
+        let response = warp::test::request().path(path).reply(&routes).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn switch_pages_hold_at_least_one_switch() {
+        let page = switches("/v2/systems/sysid/switches?limit=0").await;
+
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0]["id"], "newest");
+    }
+
+    #[tokio::test]
+    async fn switch_pages_are_capped() {
+        assert_eq!(switches("/v2/systems/sysid/switches?limit=1000").await.len(), 2);
+        assert_eq!(switches("/v2/systems/sysid/switches").await.len(), 2);
+    }
+}
@@ -0,0 +1,41 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+type Call = Arc<OnceCell<Arc<dyn Any + Send + Sync>>>;
+
+// Deduplicates concurrent identical requests so they share a single upstream call
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    calls: Mutex<HashMap<String, Call>>,
+}
+
+impl SingleFlight {
+    pub async fn run<T, F>(&self, key: String, call: F) -> T
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Future<Output = T>,
+    {
+        let cell = self.calls.lock().unwrap().entry(key.clone()).or_default().clone();
+
+        // If the caller running the call is cancelled, one of the waiters takes over
+        let value = cell
+            .get_or_init(|| async { Arc::new(call.await) as Arc<dyn Any + Send + Sync> })
+            .await
+            .clone();
+
+        {
+            let mut calls = self.calls.lock().unwrap();
+            if calls.get(&key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+                calls.remove(&key);
+            }
+        }
+
+        value
+            .downcast_ref::<T>()
+            .expect("single flight keys must map to a single result type")
+            .clone()
+    }
+}
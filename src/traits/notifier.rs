@@ -1,84 +1,170 @@
+use std::future::Future;
+use std::time::Duration;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use crate::models::*;
 
+tokio::task_local! {
+    static FETCHED_AGO: Duration;
+}
+
+// Notifies data a lower cache layer already held, so it's stored as fetched when it was rather than now
+pub async fn fetched_ago<F: Future>(age: Duration, notification: F) -> F::Output {
+    FETCHED_AGO.scope(age, notification).await
+}
+
+// How long ago the data being notified was fetched from the origin
+pub fn notified_age() -> Duration {
+    FETCHED_AGO.try_with(|age| *age).unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Entity {
+    System,
+    Member,
+    Group,
+    Message,
+}
+
 #[async_trait]
 pub trait Notifier {
-    async fn notify_system(&mut self, system: &System);
-    async fn notify_system_settings(&mut self, system: &str, settings: &SystemSettings);
-    async fn notify_system_guild_settings(&mut self, system: &str, guild: &str, settings: &SystemGuildSettings);
-    async fn notify_system_autoproxy(&mut self, system: &str, settings: &AutoproxySettings);
-    async fn notify_system_members(&mut self, system: &str, members: &Vec<Member>);
-    async fn notify_member(&mut self, member: &Member);
-    async fn notify_member_groups(&mut self, member: &str, groups: &Vec<Group>);
-    async fn notify_member_guild_settings(&mut self, member: &str, guild: &str, settings: &MemberGuildSettings);
-    async fn notify_system_groups(&mut self, system: &str, groups: &Vec<Group>);
-    async fn notify_group(&mut self, group: &Group);
-    async fn notify_group_members(&mut self, group: &str, members: &Vec<Member>);
-    async fn notify_system_switches(&mut self, system: &str, switches: &Vec<Switch>);
-    async fn notify_system_active_switch(&mut self, system: &str, switch: &Switch);
-    async fn notify_switch(&mut self, system: &str, switch: &Switch);
-    async fn notify_message(&mut self, message: &Message);
+    async fn notify_system(&mut self, token: Option<&str>, system: &System);
+    async fn notify_system_settings(&mut self, token: Option<&str>, system: &str, settings: &SystemSettings);
+    async fn notify_system_guild_settings(&mut self, token: Option<&str>, system: &str, guild: &str, settings: &SystemGuildSettings);
+    async fn notify_system_autoproxy(&mut self, token: Option<&str>, system: &str, settings: &AutoproxySettings);
+    async fn notify_system_members(&mut self, token: Option<&str>, system: &str, members: &[Member]);
+    async fn notify_member(&mut self, token: Option<&str>, member: &Member);
+    async fn notify_member_groups(&mut self, token: Option<&str>, member: &str, groups: &[Group]);
+    async fn notify_member_guild_settings(&mut self, token: Option<&str>, member: &str, guild: &str, settings: &MemberGuildSettings);
+    async fn notify_system_groups(&mut self, token: Option<&str>, system: &str, groups: &[Group]);
+    async fn notify_group(&mut self, token: Option<&str>, group: &Group);
+    async fn notify_group_members(&mut self, token: Option<&str>, group: &str, members: &[Member]);
+    async fn notify_system_switches(&mut self, token: Option<&str>, system: &str, before: &str, limit: u64, switches: &[Switch]);
+    async fn notify_system_fronters(&mut self, token: Option<&str>, system: &str, switch: &Switch);
+    async fn notify_switch(&mut self, token: Option<&str>, system: &str, switch: &Switch);
+    async fn notify_message(&mut self, token: Option<&str>, message: &Message);
+    async fn notify_not_found(&mut self, token: Option<&str>, entity: Entity, id: &str);
+    // Another form of ID PluralKit accepted for an entity, such as a UUID, a Discord account or @me
+    async fn notify_alias(&mut self, token: Option<&str>, entity: Entity, alias: &str, id: &str);
+
+    // Deletions and invalidations apply to the data fetched with every token
+    async fn notify_deleted(&mut self, entity: Entity, id: &str);
+    async fn notify_switch_deleted(&mut self, system: &str, switch: &str);
+    async fn notify_invalidated(&mut self, entity: Entity, id: &str);
+    // The alias may no longer lead to the same entity, such as an unlinked Discord account
+    async fn notify_alias_invalidated(&mut self, entity: Entity, alias: &str);
+    async fn notify_system_members_invalidated(&mut self, system: &str);
+    async fn notify_system_groups_invalidated(&mut self, system: &str);
+    async fn notify_system_switches_invalidated(&mut self, system: &str);
+    async fn notify_member_groups_invalidated(&mut self, member: &str);
+
+    // Dispatch events identify entities by UUID, each layer looks them up in what it has cached
+    async fn resolve_uuid(&mut self, entity: Entity, uuid: &str) -> Option<String>;
 }
 
 #[async_trait]
-impl<'a, T: Notifier + Send> Notifier for &'a mut T {
-    async fn notify_system(&mut self, system: &System) {
-        self.notify_system(system).await;
+impl<T: Notifier + Send> Notifier for &mut T {
+    async fn notify_system(&mut self, token: Option<&str>, system: &System) {
+        (**self).notify_system(token, system).await;
+    }
+
+    async fn notify_system_settings(&mut self, token: Option<&str>, system: &str, settings: &SystemSettings) {
+        (**self).notify_system_settings(token, system, settings).await;
+    }
+
+    async fn notify_system_guild_settings(&mut self, token: Option<&str>, system: &str, guild: &str, settings: &SystemGuildSettings) {
+        (**self).notify_system_guild_settings(token, system, guild, settings).await;
+    }
+
+    async fn notify_system_autoproxy(&mut self, token: Option<&str>, system: &str, settings: &AutoproxySettings) {
+        (**self).notify_system_autoproxy(token, system, settings).await;
+    }
+
+    async fn notify_system_members(&mut self, token: Option<&str>, system: &str, members: &[Member]) {
+        (**self).notify_system_members(token, system, members).await;
+    }
+
+    async fn notify_member(&mut self, token: Option<&str>, member: &Member) {
+        (**self).notify_member(token, member).await;
+    }
+
+    async fn notify_member_groups(&mut self, token: Option<&str>, member: &str, groups: &[Group]) {
+        (**self).notify_member_groups(token, member, groups).await;
+    }
+
+    async fn notify_member_guild_settings(&mut self, token: Option<&str>, member: &str, guild: &str, settings: &MemberGuildSettings) {
+        (**self).notify_member_guild_settings(token, member, guild, settings).await;
+    }
+
+    async fn notify_system_groups(&mut self, token: Option<&str>, system: &str, groups: &[Group]) {
+        (**self).notify_system_groups(token, system, groups).await;
+    }
+
+    async fn notify_group(&mut self, token: Option<&str>, group: &Group) {
+        (**self).notify_group(token, group).await;
+    }
+
+    async fn notify_group_members(&mut self, token: Option<&str>, group: &str, members: &[Member]) {
+        (**self).notify_group_members(token, group, members).await;
+    }
+
+    async fn notify_system_switches(&mut self, token: Option<&str>, system: &str, before: &str, limit: u64, switches: &[Switch]) {
+        (**self).notify_system_switches(token, system, before, limit, switches).await;
     }
 
-    async fn notify_system_settings(&mut self, system: &str, settings: &SystemSettings) {
-        self.notify_system_settings(system, settings).await;
+    async fn notify_system_fronters(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        (**self).notify_system_fronters(token, system, switch).await;
     }
 
-    async fn notify_system_guild_settings(&mut self, system: &str, guild: &str, settings: &SystemGuildSettings) {
-        self.notify_system_guild_settings(system, guild, settings).await;
+    async fn notify_switch(&mut self, token: Option<&str>, system: &str, switch: &Switch) {
+        (**self).notify_switch(token, system, switch).await;
     }
 
-    async fn notify_system_autoproxy(&mut self, system: &str, settings: &AutoproxySettings) {
-        self.notify_system_autoproxy(system, settings).await;
+    async fn notify_message(&mut self, token: Option<&str>, message: &Message) {
+        (**self).notify_message(token, message).await;
     }
 
-    async fn notify_system_members(&mut self, system: &str, members: &Vec<Member>) {
-        self.notify_system_members(system, members).await;
+    async fn notify_not_found(&mut self, token: Option<&str>, entity: Entity, id: &str) {
+        (**self).notify_not_found(token, entity, id).await;
     }
 
-    async fn notify_member(&mut self, member: &Member) {
-        self.notify_member(member).await;
+    async fn notify_alias(&mut self, token: Option<&str>, entity: Entity, alias: &str, id: &str) {
+        (**self).notify_alias(token, entity, alias, id).await;
     }
 
-    async fn notify_member_groups(&mut self, member: &str, groups: &Vec<Group>) {
-        self.notify_member_groups(member, groups).await;
+    async fn notify_deleted(&mut self, entity: Entity, id: &str) {
+        (**self).notify_deleted(entity, id).await;
     }
 
-    async fn notify_member_guild_settings(&mut self, member: &str, guild: &str, settings: &MemberGuildSettings) {
-        self.notify_member_guild_settings(member, guild, settings).await;
+    async fn notify_switch_deleted(&mut self, system: &str, switch: &str) {
+        (**self).notify_switch_deleted(system, switch).await;
     }
 
-    async fn notify_system_groups(&mut self, system: &str, groups: &Vec<Group>) {
-        self.notify_system_groups(system, groups).await;
+    async fn notify_invalidated(&mut self, entity: Entity, id: &str) {
+        (**self).notify_invalidated(entity, id).await;
     }
 
-    async fn notify_group(&mut self, group: &Group) {
-        self.notify_group(group).await;
+    async fn notify_alias_invalidated(&mut self, entity: Entity, alias: &str) {
+        (**self).notify_alias_invalidated(entity, alias).await;
     }
 
-    async fn notify_group_members(&mut self, group: &str, members: &Vec<Member>) {
-        self.notify_group_members(group, members).await;
+    async fn notify_system_members_invalidated(&mut self, system: &str) {
+        (**self).notify_system_members_invalidated(system).await;
     }
 
-    async fn notify_system_switches(&mut self, system: &str, switches: &Vec<Switch>) {
-        self.notify_system_switches(system, switches).await;
+    async fn notify_system_groups_invalidated(&mut self, system: &str) {
+        (**self).notify_system_groups_invalidated(system).await;
     }
 
-    async fn notify_system_active_switch(&mut self, system: &str, switch: &Switch) {
-        self.notify_system_active_switch(system, switch).await;
+    async fn notify_system_switches_invalidated(&mut self, system: &str) {
+        (**self).notify_system_switches_invalidated(system).await;
     }
 
-    async fn notify_switch(&mut self, system: &str, switch: &Switch) {
-        self.notify_switch(system, switch).await;
+    async fn notify_member_groups_invalidated(&mut self, member: &str) {
+        (**self).notify_member_groups_invalidated(member).await;
     }
 
-    async fn notify_message(&mut self, message: &Message) {
-        self.notify_message(message).await;
+    async fn resolve_uuid(&mut self, entity: Entity, uuid: &str) -> Option<String> {
+        (**self).resolve_uuid(entity, uuid).await
     }
 }
\ No newline at end of file
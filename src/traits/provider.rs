@@ -1,93 +1,213 @@
+use std::fmt;
+use std::time::Duration;
 use crate::models::*;
 use async_trait::async_trait;
+use tokio::sync::Mutex;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ProviderResult<T> {
     Ok(T),
+    // Fresh data a cache already held, along with how long ago it was fetched from the origin
+    Cached(T, Duration),
     NotFound,
     Unauthorized,
-    Failed,
-    NotImplemented,
+    Failed(Failure),
+    // The provider is being rate limited, along with how long until it may be asked again
+    RateLimited(Duration),
+    // The provider has no answer and the next one should be asked
+    Miss,
+    // Expired data, along with how long ago it expired
+    Stale(T, Duration),
+}
+
+impl<T> ProviderResult<T> {
+    pub fn is_authoritative(&self) -> bool {
+        matches!(self, ProviderResult::Ok(_) | ProviderResult::Cached(..) | ProviderResult::NotFound | ProviderResult::Unauthorized)
+    }
+
+    // Keeps the most meaningful of two non-authoritative results, real failures are never hidden by a miss
+    pub fn or_worse(self, other: ProviderResult<T>) -> ProviderResult<T> {
+        match self {
+            ProviderResult::Failed(_) | ProviderResult::RateLimited(_) => self,
+            _ => other,
+        }
+    }
+}
+
+// Why a provider couldn't give an answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Timeout,
+    // Connecting, sending the request or reading the response failed
+    Transport(String),
+    // An error status without a PluralKit error in the body
+    Status(u16),
+    // PluralKit's own error, as found in the response body
+    Origin { status: u16, code: u32, message: String },
+    // The response didn't match our models, along with the path of the offending field
+    Decode { path: String, message: String },
+    // Requests to the origin are failing fast until it recovers
+    CircuitOpen,
+    // The cache's own storage failed
+    Storage(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Timeout => write!(f, "request timed out"),
+            Failure::Transport(error) => write!(f, "transport error: {}", error),
+            Failure::Status(status) => write!(f, "HTTP {}", status),
+            Failure::Origin { status, code, message } => write!(f, "HTTP {}, PluralKit error {}: {}", status, code, message),
+            Failure::Decode { path, message } => write!(f, "failed to decode `{}`: {}", path, message),
+            Failure::CircuitOpen => write!(f, "origin circuit is open"),
+            Failure::Storage(error) => write!(f, "storage error: {}", error),
+        }
+    }
 }
 
 #[async_trait]
 pub trait Provider {
-    async fn get_system(&mut self, id: &str) -> ProviderResult<System>;
-    async fn get_system_settings(&mut self, id: &str) -> ProviderResult<SystemSettings>;
-    async fn get_system_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings>;
-    async fn get_system_autoproxy(&mut self, id: &str) -> ProviderResult<AutoproxySettings>;
-    async fn get_system_members(&mut self, id: &str) -> ProviderResult<Vec<Member>>;
-    async fn get_member(&mut self, id: &str) -> ProviderResult<Member>;
-    async fn get_member_groups(&mut self, id: &str) -> ProviderResult<Vec<Group>>;
-    async fn get_member_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings>;
-    async fn get_system_groups(&mut self, id: &str, with_member: bool) -> ProviderResult<Vec<Group>>;
-    async fn get_group(&mut self, id: &str) -> ProviderResult<Group>;
-    async fn get_group_members(&mut self, id: &str) -> ProviderResult<Vec<Member>>;
-    async fn get_system_switches(&mut self, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>>;
-    async fn get_system_active_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch>;
-    async fn get_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch>;
-    async fn get_message(&mut self, id: &str) -> ProviderResult<Message>;
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System>;
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings>;
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings>;
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings>;
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>>;
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member>;
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>>;
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings>;
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>>;
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group>;
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>>;
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>>;
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch>;
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch>;
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message>;
 }
 
 #[async_trait]
 impl<T: Provider + Send + Sync> Provider for &'static mut T {
-    async fn get_system(&mut self, id: &str) -> ProviderResult<System> {
-        self.get_system(id).await
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        (**self).get_system(token, id).await
+    }
+
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        (**self).get_system_settings(token, id).await
+    }
+
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        (**self).get_system_guild_settings(token, id, guild).await
+    }
+
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        (**self).get_system_autoproxy(token, id).await
+    }
+
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        (**self).get_system_members(token, id).await
+    }
+
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        (**self).get_member(token, id).await
+    }
+
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        (**self).get_member_groups(token, id).await
+    }
+
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        (**self).get_member_guild_settings(token, id, guild).await
+    }
+
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        (**self).get_system_groups(token, id, with_member).await
+    }
+
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        (**self).get_group(token, id).await
+    }
+
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        (**self).get_group_members(token, id).await
+    }
+
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        (**self).get_system_switches(token, id, before, limit).await
     }
 
-    async fn get_system_settings(&mut self, id: &str) -> ProviderResult<SystemSettings> {
-        self.get_system_settings(id).await
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        (**self).get_system_fronters(token, id).await
     }
 
-    async fn get_system_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
-        self.get_system_guild_settings(id, guild).await
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        (**self).get_switch(token, id, switch_id).await
     }
 
-    async fn get_system_autoproxy(&mut self, id: &str) -> ProviderResult<AutoproxySettings> {
-        self.get_system_autoproxy(id).await
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        (**self).get_message(token, id).await
+    }
+}
+
+// Caches are locked for the time of a lookup, providers which can be shared as they are skip the lock
+#[async_trait]
+impl<T: Provider + Send + Sync> Provider for Mutex<T> {
+    async fn get_system(&self, token: Option<&str>, id: &str) -> ProviderResult<System> {
+        self.lock().await.get_system(token, id).await
+    }
+
+    async fn get_system_settings(&self, token: Option<&str>, id: &str) -> ProviderResult<SystemSettings> {
+        self.lock().await.get_system_settings(token, id).await
     }
 
-    async fn get_system_members(&mut self, id: &str) -> ProviderResult<Vec<Member>> {
-        self.get_system_members(id).await
+    async fn get_system_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<SystemGuildSettings> {
+        self.lock().await.get_system_guild_settings(token, id, guild).await
     }
 
-    async fn get_member(&mut self, id: &str) -> ProviderResult<Member> {
-        self.get_member(id).await
+    async fn get_system_autoproxy(&self, token: Option<&str>, id: &str) -> ProviderResult<AutoproxySettings> {
+        self.lock().await.get_system_autoproxy(token, id).await
     }
 
-    async fn get_member_groups(&mut self, id: &str) -> ProviderResult<Vec<Group>> {
-        self.get_member_groups(id).await
+    async fn get_system_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        self.lock().await.get_system_members(token, id).await
     }
 
-    async fn get_member_guild_settings(&mut self, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
-        self.get_member_guild_settings(id, guild).await
+    async fn get_member(&self, token: Option<&str>, id: &str) -> ProviderResult<Member> {
+        self.lock().await.get_member(token, id).await
     }
 
-    async fn get_system_groups(&mut self, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
-        self.get_system_groups(id, with_member).await
+    async fn get_member_groups(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Group>> {
+        self.lock().await.get_member_groups(token, id).await
     }
 
-    async fn get_group(&mut self, id: &str) -> ProviderResult<Group> {
-        self.get_group(id).await
+    async fn get_member_guild_settings(&self, token: Option<&str>, id: &str, guild: &str) -> ProviderResult<MemberGuildSettings> {
+        self.lock().await.get_member_guild_settings(token, id, guild).await
     }
 
-    async fn get_group_members(&mut self, id: &str) -> ProviderResult<Vec<Member>> {
-        self.get_group_members(id).await
+    async fn get_system_groups(&self, token: Option<&str>, id: &str, with_member: bool) -> ProviderResult<Vec<Group>> {
+        self.lock().await.get_system_groups(token, id, with_member).await
     }
 
-    async fn get_system_switches(&mut self, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
-        self.get_system_switches(id, before, limit).await
+    async fn get_group(&self, token: Option<&str>, id: &str) -> ProviderResult<Group> {
+        self.lock().await.get_group(token, id).await
     }
 
-    async fn get_system_active_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch> {
-        self.get_system_active_switch(id, switch_id).await
+    async fn get_group_members(&self, token: Option<&str>, id: &str) -> ProviderResult<Vec<Member>> {
+        self.lock().await.get_group_members(token, id).await
     }
 
-    async fn get_switch(&mut self, id: &str, switch_id: &str) -> ProviderResult<Switch> {
-        self.get_switch(id, switch_id).await
+    async fn get_system_switches(&self, token: Option<&str>, id: &str, before: &str, limit: u64) -> ProviderResult<Vec<Switch>> {
+        self.lock().await.get_system_switches(token, id, before, limit).await
     }
 
-    async fn get_message(&mut self, id: &str) -> ProviderResult<Message> {
-        self.get_message(id).await
+    async fn get_system_fronters(&self, token: Option<&str>, id: &str) -> ProviderResult<Switch> {
+        self.lock().await.get_system_fronters(token, id).await
     }
-}
\ No newline at end of file
+
+    async fn get_switch(&self, token: Option<&str>, id: &str, switch_id: &str) -> ProviderResult<Switch> {
+        self.lock().await.get_switch(token, id, switch_id).await
+    }
+
+    async fn get_message(&self, token: Option<&str>, id: &str) -> ProviderResult<Message> {
+        self.lock().await.get_message(token, id).await
+    }
+}